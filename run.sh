@@ -6,6 +6,8 @@ NETCATFILE=out/netcat
 SERVERFILE=out/server
 MANIFEST=tcpst/Cargo.toml
 BINARY=tcpst/target/release/tcp-st
+CHECKER=tcpst/target/release/tcp-st-pcap
+MODEL=tcp.ctx
 PORT=49155
 IP=127.0.0.1
 
@@ -29,5 +31,6 @@ sudo -i nc $IP -v $PORT -w 1 & > $NETCATFILE
 
 sleep 2;
 tcpdump -r $TCPDUMPFILE 
+./$CHECKER $MODEL $TCPDUMPFILE
 sudo iptables -D OUTPUT -p tcp --tcp-flags RST RST -j DROP
 exit 0
@@ -0,0 +1,109 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{env, fs, path::Path};
+
+use tcp_st_model::{
+    codegen::{messages, session_type, session_type_with_dual},
+    ctx::Context,
+    global::Global,
+};
+
+// The protocol of the example server, projected onto the local types used in `main.rs`.
+const PROTOCOL: &str = "../tcp_handshake.global";
+
+// The model of TCP, whose payload sorts become the messages of `tcp_st::sorts`.
+const MODEL: &str = "../tcp.ctx";
+
+// The payload sorts of the model that are TCP segments, with the messages of `NetChannel`
+// that validate them. The other sorts are generated as stubs.
+const SEGMENTS: &[(&str, &str)] = &[
+    ("SegSynSet", "::tcp_st::net_channel::Syn"),
+    ("SegSynAckSet", "::tcp_st::net_channel::SynAck"),
+    ("SegAckSet", "::tcp_st::net_channel::Ack"),
+    ("SegFinSet", "::tcp_st::net_channel::Fin"),
+    ("SegFinAckSet", "::tcp_st::net_channel::FinAck"),
+    ("SegRstSet", "::tcp_st::net_channel::Rst"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed={MODEL}");
+    let source = fs::read_to_string(MODEL).expect("failed to read the model");
+    let model = Context::parse(&source).unwrap_or_else(|e| panic!("{MODEL}:{e}"));
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(
+        Path::new(&out_dir).join("sorts.rs"),
+        messages(&model, "tcp.ctx", SEGMENTS),
+    )
+    .expect("failed to write the generated messages");
+
+    // The TCP client is typed against the local types of `tcp.ctx` as they are,
+    // and its end of the channel to the user by the dual of the user's type.
+    // That dual must be the actions of `client_system` with the user.
+    let entry = |role: &str| {
+        model
+            .entry(role)
+            .unwrap_or_else(|| panic!("{MODEL}: no entry for `{role}`"))
+            .local
+            .to_local()
+    };
+    let client_system = entry("client_system");
+    let client_user = entry("client_user");
+    let client_system_user_view = client_system
+        .restrict("client_user")
+        .unwrap_or_else(|e| panic!("{MODEL}: {e}"));
+    let client_user_dual = client_user
+        .dual("client_user")
+        .unwrap_or_else(|e| panic!("{MODEL}: {e}"));
+    assert!(
+        client_system_user_view == client_user_dual,
+        "{MODEL}: the actions of `client_system` with `client_user` are `{client_system_user_view}`, not the dual of `client_user`, `{client_user_dual}`"
+    );
+    let generated = [
+        session_type("ClientSystemSessionType", &client_system),
+        session_type_with_dual(
+            "ClientUserSessionType",
+            &client_user,
+            "ClientSystemUserView",
+            "client_user",
+        ),
+    ]
+    .concat();
+    fs::write(Path::new(&out_dir).join("client.rs"), generated)
+        .expect("failed to write the generated session types");
+
+    println!("cargo:rerun-if-changed={PROTOCOL}");
+    let source = fs::read_to_string(PROTOCOL).expect("failed to read the protocol");
+    let protocol = Global::parse(&source).unwrap_or_else(|e| panic!("{PROTOCOL}:{e}"));
+    let project = |role: &str| {
+        protocol
+            .project(role)
+            .unwrap_or_else(|e| panic!("{PROTOCOL}: {e}"))
+    };
+
+    let server_system = project("server_system");
+    let server_user = project("server_user");
+    let server_system_user_view = server_system
+        .restrict("server_user")
+        .unwrap_or_else(|e| panic!("{PROTOCOL}: {e}"));
+
+    let generated = [
+        session_type("ServerSystemSessionType", &server_system),
+        session_type("ServerUserSessionType", &server_user),
+        session_type("ServerSystemUserView", &server_system_user_view),
+    ]
+    .concat();
+    let out =
+        Path::new(&env::var("OUT_DIR").expect("OUT_DIR is set by cargo")).join("handshake.rs");
+    fs::write(out, generated).expect("failed to write the generated session types");
+}
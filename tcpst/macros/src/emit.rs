@@ -0,0 +1,216 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::collections::HashSet;
+
+use tcp_st_model::{
+    ctx::{Branch, Type},
+    lexer::Name,
+};
+
+use crate::parse::{Declaration, Error};
+
+/// Turn a `snake_case` name from the model into `CamelCase`.
+pub fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// The Rust name of the role `name` of the model, e.g. `server_system` is `RoleServerSystem`.
+pub fn role(name: &str) -> String {
+    format!("Role{}", camel_case(name))
+}
+
+/// [Emitter] expands a [Declaration] into the `tcp_st` action types.
+/// Besides the type alias itself it emits an enum for every choice of more than one branch,
+/// named after the alias and numbered in order of appearance, e.g. `ServerUserSessionTypeChoice0`,
+/// and a `tcp_st::RecursionVariable` marker for every `mu(t)`,
+/// named after the alias and the variable, e.g. `ServerUserSessionTypeT`.
+///
+/// If the declaration has a dual, every enum is declared with the `dual` form of `tcp_st::choice!`
+/// and every marker gets a dual marker, named after the dual alias in the same way,
+/// which implements `tcp_st::Dual` for the whole local type. This needs every action
+/// of the local type to be with the same peer.
+pub struct Emitter<'a> {
+    declaration: &'a Declaration,
+    items: Vec<String>,
+    names: HashSet<String>,
+    choices: usize,
+    loops: Vec<(String, String)>,
+    markers: Vec<(String, String, String)>,
+    peer: Option<String>,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn emit(declaration: &'a Declaration) -> Result<String, Error> {
+        let mut emitter = Emitter {
+            declaration,
+            items: Vec::new(),
+            names: HashSet::new(),
+            choices: 0,
+            loops: Vec::new(),
+            markers: Vec::new(),
+            peer: None,
+        };
+        let local = emitter.local(&declaration.local)?;
+        emitter.items.push(format!(
+            "{} {} type {} = {local};",
+            declaration.attributes, declaration.visibility, declaration.name
+        ));
+        if let Some(dual) = &declaration.dual {
+            let owner = role(&dual.role.to_string());
+            let visibility = &dual.visibility;
+            for (marker, variable, body) in std::mem::take(&mut emitter.markers) {
+                let dual_marker = emitter.fresh(&format!("{}{variable}", dual.name));
+                emitter.items.push(format!(
+                    "{visibility} struct {dual_marker};
+                    impl ::tcp_st::Dual<{owner}> for {marker} {{ type Output = {dual_marker}; }}
+                    impl ::tcp_st::RecursionVariable for {dual_marker} {{
+                        type Body = <{body} as ::tcp_st::Dual<{owner}>>::Output;
+                    }}"
+                ));
+                if let Some(peer) = &emitter.peer {
+                    emitter.items.push(format!(
+                        "impl ::tcp_st::Dual<{peer}> for {dual_marker} {{ type Output = {marker}; }}"
+                    ));
+                }
+            }
+            emitter.items.push(format!(
+                "{visibility} type {} = <{} as ::tcp_st::Dual<{owner}>>::Output;",
+                dual.name, declaration.name
+            ));
+        }
+        Ok(emitter.items.join("\n"))
+    }
+
+    fn local(&mut self, local: &Type) -> Result<String, Error> {
+        match local {
+            Type::End(_) => Ok("::tcp_st::End".to_string()),
+            Type::Var(variable) => {
+                match self
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|(bound, _)| *bound == variable.name)
+                {
+                    Some((_, marker)) => Ok(format!("::tcp_st::Var<{marker}>")),
+                    None => Err(Error::new(
+                        self.declaration.span(variable.span),
+                        format!("recursion variable `{variable}` is not bound by any `mu`"),
+                    )),
+                }
+            }
+            Type::Rec { variable, body, .. } => {
+                let marker = self.fresh(&format!(
+                    "{}{}",
+                    self.declaration.name,
+                    camel_case(&variable.name)
+                ));
+                self.loops.push((variable.name.clone(), marker.clone()));
+                let body = self.local(body)?;
+                self.loops.pop();
+                let visibility = &self.declaration.visibility;
+                self.items.push(format!(
+                    "{visibility} struct {marker};
+                    impl ::tcp_st::RecursionVariable for {marker} {{ type Body = {body}; }}"
+                ));
+                self.markers
+                    .push((marker.clone(), camel_case(&variable.name), body));
+                Ok(format!("::tcp_st::Rec<{marker}>"))
+            }
+            Type::Select { peer, branches, .. } => self.choice(peer, branches, "Select"),
+            Type::Offer { peer, branches, .. } => self.choice(peer, branches, "Offer"),
+        }
+    }
+
+    /// A single branch is an `OfferOne` or `SelectOne`,
+    /// any more become an `OfferN` or `SelectN` over a generated enum.
+    fn choice(&mut self, peer: &Name, branches: &[Branch], action: &str) -> Result<String, Error> {
+        let peer_name = peer;
+        let peer = role(&peer.name);
+        if self.declaration.dual.is_some() {
+            match &self.peer {
+                Some(other) if *other != peer => {
+                    return Err(Error::new(
+                        self.declaration.span(peer_name.span),
+                        format!(
+                            "the dual of `{}` needs the actions of a single peer, but `{peer_name}` is not the first peer",
+                            self.declaration.name
+                        ),
+                    ))
+                }
+                _ => self.peer = Some(peer.clone()),
+            }
+        }
+        if let [branch] = branches {
+            let continuation = self.local(&branch.continuation)?;
+            return Ok(format!(
+                "::tcp_st::{action}One<{peer}, {}, {continuation}>",
+                branch.sort
+            ));
+        }
+        let index = self.choices;
+        let name = self.fresh(&format!("{}Choice{index}", self.declaration.name));
+        self.choices += 1;
+        let mut labels = HashSet::new();
+        let mut variants = Vec::new();
+        for branch in branches {
+            // distinct labels of the model may still be the same variant, e.g. `a_b` and `a__b`
+            let label = camel_case(&branch.label.name);
+            if !labels.insert(label.clone()) {
+                return Err(Error::new(
+                    self.declaration.span(branch.label.span),
+                    format!(
+                        "label `{}` is a second variant `{label}` of the same choice",
+                        branch.label
+                    ),
+                ));
+            }
+            let continuation = self.local(&branch.continuation)?;
+            variants.push(format!("{label}({}, {continuation})", branch.sort));
+        }
+        let dual = match &self.declaration.dual {
+            Some(dual) => format!(
+                "dual {} enum {} for {} => {peer};",
+                dual.visibility,
+                self.fresh(&format!("{}Choice{index}", dual.name)),
+                role(&dual.role.to_string())
+            ),
+            None => String::new(),
+        };
+        self.items.push(format!(
+            "::tcp_st::choice! {{ {} enum {name} {{ {} }} {dual} }}",
+            self.declaration.visibility,
+            variants.join(", ")
+        ));
+        Ok(format!("::tcp_st::{action}N<{peer}, {name}>"))
+    }
+
+    /// `name`, or `name` followed by a number if it was already used.
+    fn fresh(&mut self, name: &str) -> String {
+        let mut fresh = name.to_string();
+        let mut suffix = 1;
+        while !self.names.insert(fresh.clone()) {
+            fresh = format!("{name}{suffix}");
+            suffix += 1;
+        }
+        fresh
+    }
+}
@@ -0,0 +1,88 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use emit::Emitter;
+use parse::{Error, Parser};
+
+/// Declare session types in the mpstk syntax of `tcp.ctx` and `pop3.ctx`.
+/// Each declaration is a type alias whose right-hand side is a local type:
+///
+/// ```ignore
+/// session_type! {
+///     pub type ServerUserSessionType = server_system<+>tcb_new(TcbInfo).server_system&{
+///         error_no_room(ErrorInsufficientResources).end,
+///         tcb_created(SocketFd).mu(t)(server_system&read_queue(Data).t)
+///     };
+/// }
+/// ```
+///
+/// expands into the action types of `tcp_st`:
+///
+/// * `role<+>label(Sort).cont` is a `SelectOne<RoleName, Sort, cont>` and `role&label(Sort).cont`
+///   an `OfferOne`, where role `foo_bar` is the type `RoleFooBar`, which must be in scope.
+/// * `role<+>{...}` and `role&{...}` with more than one branch are a `SelectN` or `OfferN`
+///   over an enum declared with `tcp_st::choice!`, with one variant per label,
+///   e.g. `error_no_room` is the variant `ErrorNoRoom`.
+///   The enums are named after the alias and numbered in order of appearance, e.g. `ServerUserSessionTypeChoice0`.
+/// * `mu(t)(...)` is a `Rec` of a generated `RecursionVariable` marker named after the alias
+///   and the variable, e.g. `ServerUserSessionTypeT`, and `t` is a `Var` of that marker.
+/// * `end` is `End`.
+///
+/// A declaration may be followed by `dual <vis> type <Name> for <role>;`, where `<role>` is
+/// the role whose view the local type is, e.g. `dual pub type ServerSystemUserView for server_user;`.
+/// This declares `<Name>` as the `Dual` of the local type, with the enums and markers of the dual
+/// named after it, so the two can be used with `CrossBeamRoleChannel::pair`.
+/// The local type must only have actions with a single peer.
+///
+/// The local type is parsed by the same parser as the contexts of `tcp_st::model::ctx`,
+/// with the same checks. Payload sorts are used as the message types, so they must name
+/// types implementing `Message` that are in scope.
+/// Labels of single-branch actions are not part of the Rust type, the message type is.
+#[proc_macro]
+pub fn session_type(input: TokenStream) -> TokenStream {
+    let mut parser = Parser::new(input, Span::call_site());
+    let mut output = String::new();
+    while !parser.is_empty() {
+        let expanded = parser
+            .declaration()
+            .and_then(|declaration| Emitter::emit(&declaration));
+        match expanded {
+            Ok(expanded) => output.push_str(&expanded),
+            Err(error) => return compile_error(error),
+        }
+    }
+    output.parse().expect("expansion is valid Rust")
+}
+
+/// `compile_error!("<message>");` at the span of `error`.
+fn compile_error(error: Error) -> TokenStream {
+    let mut message = Literal::string(&error.message);
+    message.set_span(error.span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(error.span);
+    let mut arguments = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+    arguments.set_span(error.span);
+    let mut semicolon = Punct::new(';', Spacing::Alone);
+    semicolon.set_span(error.span);
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", error.span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(arguments),
+        TokenTree::Punct(semicolon),
+    ])
+}
+
+mod emit;
+mod parse;
@@ -0,0 +1,236 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use proc_macro::{token_stream, Delimiter, Ident, Spacing, Span, TokenStream, TokenTree};
+use std::{iter::Peekable, ops::Range};
+
+use tcp_st_model::{ctx::Type, lexer};
+
+/// [Declaration] is one `<vis> type <Name> = <local type>;` item of a `session_type!` invocation.
+/// The local type is parsed by the `.ctx` parser of `tcp_st_model`, from the text of its tokens.
+pub struct Declaration {
+    pub attributes: TokenStream,
+    pub visibility: TokenStream,
+    pub name: Ident,
+    pub local: Type,
+    pub dual: Option<DualDeclaration>,
+    source: Source,
+}
+
+/// [DualDeclaration] is the `dual <vis> type <Name> for <role>;` item that may follow a declaration,
+/// naming the dual of its local type, where `<role>` is the role the local type is the view of.
+pub struct DualDeclaration {
+    pub visibility: TokenStream,
+    pub name: Ident,
+    pub role: Ident,
+}
+
+impl Declaration {
+    /// The span of the tokens the part `span` of the local type was read from.
+    pub fn span(&self, span: lexer::Span) -> Span {
+        self.source.span(span)
+    }
+}
+
+/// [Source] is the text of the tokens of a local type,
+/// with the byte range each token takes up in it.
+struct Source {
+    text: String,
+    tokens: Vec<(Range<usize>, Span)>,
+    end: Span,
+}
+
+impl Source {
+    fn new(stream: TokenStream, end: Span) -> Self {
+        let mut source = Source {
+            text: String::new(),
+            tokens: Vec::new(),
+            end,
+        };
+        source.push_stream(stream);
+        source
+    }
+
+    fn push_stream(&mut self, stream: TokenStream) {
+        for token in stream {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.push_stream(group.stream());
+                    self.push(close, group.span_close());
+                    self.text.push(' ');
+                }
+                TokenTree::Punct(punct) => {
+                    self.push(&punct.as_char().to_string(), punct.span());
+                    // `<+>` is three joint punctuation tokens
+                    if punct.spacing() == Spacing::Alone {
+                        self.text.push(' ');
+                    }
+                }
+                token => {
+                    self.push(&token.to_string(), token.span());
+                    self.text.push(' ');
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.tokens.push((start..self.text.len(), span));
+    }
+
+    /// The span of the token at the start of `span`, or the end of the input past the last token.
+    fn span(&self, span: lexer::Span) -> Span {
+        let offset = span.start.offset;
+        self.tokens
+            .iter()
+            .find(|(range, _)| range.contains(&offset))
+            .map_or(self.end, |(_, span)| *span)
+    }
+}
+
+/// [Error] is a parse or expansion error, reported at the span it points to.
+pub struct Error {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Error {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+pub struct Parser {
+    tokens: Peekable<token_stream::IntoIter>,
+    span: Span,
+}
+
+impl Parser {
+    pub fn new(input: TokenStream, span: Span) -> Self {
+        Parser {
+            tokens: input.into_iter().peekable(),
+            span,
+        }
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.tokens.peek().is_none()
+    }
+
+    /// Parse a `<attributes> <vis> type <Name> = <local type>;` declaration,
+    /// followed by an optional `dual <vis> type <Name> for <role>;`.
+    pub fn declaration(&mut self) -> Result<Declaration, Error> {
+        let mut attributes = TokenStream::new();
+        while self.peek_punct('#') {
+            let hash = self.next("`#`")?;
+            let attribute = self.next("an attribute")?;
+            attributes.extend([hash, attribute]);
+        }
+        let visibility = self.visibility()?;
+        let name = self.ident("the name of the session type")?;
+        self.punct('=')?;
+        let mut local = TokenStream::new();
+        while !self.peek_punct(';') {
+            let token = self.next("`;`")?;
+            local.extend([token]);
+        }
+        self.punct(';')?;
+        let source = Source::new(local, self.span);
+        let local = Type::parse(&source.text)
+            .map_err(|error| Error::new(source.span(error.span), error.message))?;
+        let dual = if self.peek_ident("dual") {
+            self.next("`dual`")?;
+            let visibility = self.visibility()?;
+            let name = self.ident("the name of the dual session type")?;
+            match self.next("`for`")? {
+                TokenTree::Ident(ident) if ident.to_string() == "for" => {}
+                token => return Err(Error::new(token.span(), "expected `for`")),
+            }
+            let role = self.ident("the role of the session type")?;
+            self.punct(';')?;
+            Some(DualDeclaration {
+                visibility,
+                name,
+                role,
+            })
+        } else {
+            None
+        };
+        Ok(Declaration {
+            attributes,
+            visibility,
+            name,
+            local,
+            dual,
+            source,
+        })
+    }
+
+    /// The tokens up to and excluding the next `type`.
+    fn visibility(&mut self) -> Result<TokenStream, Error> {
+        let mut visibility = TokenStream::new();
+        loop {
+            match self.next("`type`")? {
+                TokenTree::Ident(ident) if ident.to_string() == "type" => return Ok(visibility),
+                token => visibility.extend([token]),
+            }
+        }
+    }
+
+    fn next(&mut self, expected: &str) -> Result<TokenTree, Error> {
+        match self.tokens.next() {
+            Some(token) => {
+                self.span = token.span();
+                Ok(token)
+            }
+            None => Err(Error::new(
+                self.span,
+                format!("unexpected end of input, expected {expected}"),
+            )),
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<Ident, Error> {
+        match self.next(expected)? {
+            TokenTree::Ident(ident) => Ok(ident),
+            token => Err(Error::new(token.span(), format!("expected {expected}"))),
+        }
+    }
+
+    fn punct(&mut self, expected: char) -> Result<(), Error> {
+        match self.next(&format!("`{expected}`"))? {
+            TokenTree::Punct(punct) if punct.as_char() == expected => Ok(()),
+            token => Err(Error::new(token.span(), format!("expected `{expected}`"))),
+        }
+    }
+
+    fn peek_ident(&mut self, expected: &str) -> bool {
+        matches!(self.tokens.peek(), Some(TokenTree::Ident(ident)) if ident.to_string() == expected)
+    }
+
+    fn peek_punct(&mut self, expected: char) -> bool {
+        matches!(self.tokens.peek(), Some(TokenTree::Punct(punct)) if punct.as_char() == expected)
+    }
+}
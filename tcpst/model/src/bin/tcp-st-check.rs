@@ -0,0 +1,124 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{env, fs, process::ExitCode};
+
+use tcp_st_model::{
+    check::{check, Semantics, Verdict},
+    ctx::Context,
+};
+
+const USAGE: &str = "usage: tcp-st-check [--async[=BOUND]] [--properties=safety,deadlock-freedom,termination] FILE...";
+
+/// Check `.ctx` models for safety, deadlock freedom and termination,
+/// printing a counterexample trace for every property that does not hold.
+/// Exits with a failure if any of the checked properties is violated.
+fn main() -> ExitCode {
+    let mut semantics = Semantics::Synchronous;
+    let mut properties = vec!["safety", "deadlock-freedom", "termination"];
+    let mut files = Vec::new();
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    for argument in &arguments {
+        if argument == "--async" {
+            semantics = Semantics::Asynchronous { bound: 2 };
+        } else if let Some(bound) = argument.strip_prefix("--async=") {
+            match bound.parse() {
+                Ok(bound) => semantics = Semantics::Asynchronous { bound },
+                Err(_) => {
+                    eprintln!("invalid queue bound `{bound}`\n{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if let Some(list) = argument.strip_prefix("--properties=") {
+            properties = list.split(',').collect();
+            if let Some(unknown) = properties
+                .iter()
+                .find(|p| !["safety", "deadlock-freedom", "termination"].contains(p))
+            {
+                eprintln!("unknown property `{unknown}`\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        } else if argument.starts_with('-') {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        } else {
+            files.push(argument);
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut violated = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let context = match Context::parse(&source) {
+            Ok(context) => context,
+            Err(e) => {
+                eprintln!("{file}:{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        for report in check(&context, semantics) {
+            println!(
+                "{file}, session {} ({}): {} states{}",
+                report.session,
+                report.semantics,
+                report.states,
+                if report.truncated {
+                    ", some sends cut off by the queue bound"
+                } else {
+                    ""
+                }
+            );
+            let verdicts = [
+                ("safety", &report.safety),
+                ("deadlock-freedom", &report.deadlock_freedom),
+                ("termination", &report.termination),
+            ];
+            for (property, verdict) in verdicts {
+                if !properties.contains(&property) {
+                    continue;
+                }
+                match verdict {
+                    Verdict::Holds => println!("  {property}: holds"),
+                    Verdict::Violated(counterexample) => {
+                        violated = true;
+                        println!("  {property}: violated, {}", counterexample.reason);
+                        for step in &counterexample.trace {
+                            println!("    {step}");
+                        }
+                        if !counterexample.cycle.is_empty() {
+                            println!("    repeating:");
+                            for step in &counterexample.cycle {
+                                println!("      {step}");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if violated {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,69 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{env, fs, process::ExitCode};
+
+use tcp_st_model::{
+    ctx::Context,
+    export::{dot, mcrl2, mermaid, Automaton},
+};
+
+const USAGE: &str = "usage: tcp-st-export --format=dot|mermaid|mcrl2 FILE";
+
+/// Print the communicating automata of a `.ctx` model as a DOT graph,
+/// a Mermaid state diagram or an mCRL2 process specification.
+fn main() -> ExitCode {
+    let mut format = None;
+    let mut file = None;
+    for argument in env::args().skip(1) {
+        match argument.strip_prefix("--format=") {
+            Some(name) => format = Some(name.to_string()),
+            None if file.is_none() && !argument.starts_with('-') => file = Some(argument),
+            None => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let (Some(format), Some(file)) = (format, file) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let export = match format.as_str() {
+        "dot" => dot,
+        "mermaid" => mermaid,
+        "mcrl2" => mcrl2,
+        _ => {
+            eprintln!("unknown format `{format}`\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let source = match fs::read_to_string(&file) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{file}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match Context::parse(&source) {
+        Ok(context) => {
+            print!("{}", export(&Automaton::of_context(&context)));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{file}:{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,581 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use crate::{ctx::Context, local::Local};
+
+/// [Semantics] is how the participants of a context communicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Semantics {
+    /// A message is sent and received in a single step.
+    Synchronous,
+    /// Messages are sent into a FIFO queue per ordered pair of roles and received later.
+    /// A queue holds at most `bound` messages, which keeps the state space finite.
+    Asynchronous { bound: usize },
+}
+
+impl fmt::Display for Semantics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Semantics::Synchronous => write!(f, "synchronous"),
+            Semantics::Asynchronous { bound } => write!(f, "asynchronous, queues of {bound}"),
+        }
+    }
+}
+
+/// [Step] is a transition between two states of a context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `from` sends `label(sort)` to `to`, which receives it at once.
+    Communicate {
+        from: String,
+        to: String,
+        label: String,
+        sort: String,
+    },
+    /// `from` puts `label(sort)` in its queue to `to`.
+    Send {
+        from: String,
+        to: String,
+        label: String,
+        sort: String,
+    },
+    /// `to` takes `label(sort)` out of its queue from `from`.
+    Receive {
+        from: String,
+        to: String,
+        label: String,
+        sort: String,
+    },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Communicate {
+                from,
+                to,
+                label,
+                sort,
+            } => write!(f, "{from}->{to}:{label}({sort})"),
+            Step::Send {
+                from,
+                to,
+                label,
+                sort,
+            } => write!(f, "{from}!{to}:{label}({sort})"),
+            Step::Receive {
+                from,
+                to,
+                label,
+                sort,
+            } => write!(f, "{to}?{from}:{label}({sort})"),
+        }
+    }
+}
+
+/// [Counterexample] is a run of the context that violates a property.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    /// What is wrong with the state the trace ends in.
+    pub reason: String,
+    /// The steps from the initial state to the offending state.
+    pub trace: Vec<Step>,
+    /// For runs that never end, the steps that lead from the offending state back to itself.
+    pub cycle: Vec<Step>,
+}
+
+/// [Verdict] is whether a property holds, or a [Counterexample] if it does not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Holds,
+    Violated(Counterexample),
+}
+
+impl Verdict {
+    pub fn holds(&self) -> bool {
+        *self == Verdict::Holds
+    }
+}
+
+/// [Report] is the outcome of checking one session of a context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub session: String,
+    pub semantics: Semantics,
+    /// The number of reachable states.
+    pub states: usize,
+    /// Whether some sends were cut off by the queue bound of [Semantics::Asynchronous],
+    /// in which case the verdicts only cover runs that stay within the bound.
+    pub truncated: bool,
+    /// No participant ever receives a label it does not offer, or one with a different payload sort.
+    pub safety: Verdict,
+    /// Every state with no way forward is the one where all participants reached `end`
+    /// and no message is left in a queue.
+    pub deadlock_freedom: Verdict,
+    /// Every run is finite.
+    pub termination: Verdict,
+}
+
+/// Check every session of `context` by exploring its state space under `semantics`.
+pub fn check(context: &Context, semantics: Semantics) -> Vec<Report> {
+    let mut sessions: Vec<&str> = Vec::new();
+    for entry in &context.entries {
+        if !sessions.contains(&entry.session.name.as_str()) {
+            sessions.push(&entry.session.name);
+        }
+    }
+    sessions
+        .into_iter()
+        .map(|session| {
+            let (roles, locals): (Vec<_>, Vec<_>) = context
+                .entries
+                .iter()
+                .filter(|entry| entry.session.name == session)
+                .map(|entry| (entry.role.name.clone(), entry.local.to_local().unfold()))
+                .unzip();
+            let mut explorer = Explorer::new(roles, semantics);
+            explorer.explore(State {
+                queues: vec![VecDeque::new(); locals.len() * locals.len()],
+                locals,
+            });
+            explorer.report(session)
+        })
+        .collect()
+}
+
+/// [State] is the local type of every participant, unfolded, and the queues between them.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    locals: Vec<Local>,
+    /// The queue from `p` to `q` is at `p * n + q`, always empty under [Semantics::Synchronous].
+    queues: Vec<VecDeque<(String, String)>>,
+}
+
+struct Explorer {
+    roles: Vec<String>,
+    semantics: Semantics,
+    states: Vec<State>,
+    index: HashMap<State, usize>,
+    edges: Vec<Vec<(Step, usize)>>,
+    parents: Vec<Option<(usize, Step)>>,
+    unsafe_state: Option<(usize, String)>,
+    deadlock: Option<(usize, String)>,
+    truncated: bool,
+}
+
+impl Explorer {
+    fn new(roles: Vec<String>, semantics: Semantics) -> Self {
+        Explorer {
+            roles,
+            semantics,
+            states: Vec::new(),
+            index: HashMap::new(),
+            edges: Vec::new(),
+            parents: Vec::new(),
+            unsafe_state: None,
+            deadlock: None,
+            truncated: false,
+        }
+    }
+
+    /// Explore breadth first, so that the first violation found has a shortest trace.
+    fn explore(&mut self, initial: State) {
+        self.insert(initial, None);
+        let mut next = 0;
+        while next < self.states.len() {
+            let state = self.states[next].clone();
+            let (successors, errors, blocked) = self.successors(&state);
+            if let Some(error) = errors.into_iter().next() {
+                self.unsafe_state.get_or_insert((next, error));
+            }
+            self.truncated |= blocked;
+            if successors.is_empty() && !blocked && !self.is_final(&state) {
+                let reason = self.stuck(&state);
+                self.deadlock.get_or_insert((next, reason));
+            }
+            let mut edges = Vec::new();
+            for (step, successor) in successors {
+                let target = self.insert(successor, Some((next, step.clone())));
+                edges.push((step, target));
+            }
+            self.edges[next] = edges;
+            next += 1;
+        }
+    }
+
+    fn insert(&mut self, state: State, parent: Option<(usize, Step)>) -> usize {
+        if let Some(&index) = self.index.get(&state) {
+            return index;
+        }
+        let index = self.states.len();
+        self.index.insert(state.clone(), index);
+        self.states.push(state);
+        self.edges.push(Vec::new());
+        self.parents.push(parent);
+        index
+    }
+
+    fn role(&self, name: &str) -> Option<usize> {
+        self.roles.iter().position(|role| role == name)
+    }
+
+    fn is_final(&self, state: &State) -> bool {
+        state.locals.iter().all(|local| *local == Local::End)
+            && state.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// The steps possible from `state`, the safety errors of `state`,
+    /// and whether a send was cut off by the queue bound.
+    fn successors(&self, state: &State) -> (Vec<(Step, State)>, Vec<String>, bool) {
+        let n = self.roles.len();
+        let mut successors = Vec::new();
+        let mut errors = Vec::new();
+        let mut blocked = false;
+        for (p, local) in state.locals.iter().enumerate() {
+            let from = &self.roles[p];
+            match (local, self.semantics) {
+                (Local::Select { peer, branches }, Semantics::Synchronous) => {
+                    let Some(q) = self.role(peer) else { continue };
+                    let Local::Offer {
+                        peer: offered_from,
+                        branches: offered,
+                    } = &state.locals[q]
+                    else {
+                        continue;
+                    };
+                    if offered_from != from {
+                        continue;
+                    }
+                    for branch in branches {
+                        match offered.iter().find(|o| o.label == branch.label) {
+                            Some(o) if o.sort == branch.sort => {
+                                let mut successor = state.clone();
+                                successor.locals[p] = branch.continuation.unfold();
+                                successor.locals[q] = o.continuation.unfold();
+                                successors.push((
+                                    Step::Communicate {
+                                        from: from.clone(),
+                                        to: peer.clone(),
+                                        label: branch.label.clone(),
+                                        sort: branch.sort.clone(),
+                                    },
+                                    successor,
+                                ));
+                            }
+                            Some(o) => errors.push(format!(
+                                "{from} sends {}({}) to {peer}, which expects {}({})",
+                                branch.label, branch.sort, o.label, o.sort
+                            )),
+                            None => errors.push(format!(
+                                "{from} sends {}({}) to {peer}, which does not offer it",
+                                branch.label, branch.sort
+                            )),
+                        }
+                    }
+                }
+                (Local::Select { peer, branches }, Semantics::Asynchronous { bound }) => {
+                    let Some(q) = self.role(peer) else { continue };
+                    if state.queues[p * n + q].len() >= bound {
+                        blocked = true;
+                        continue;
+                    }
+                    for branch in branches {
+                        let mut successor = state.clone();
+                        successor.locals[p] = branch.continuation.unfold();
+                        successor.queues[p * n + q]
+                            .push_back((branch.label.clone(), branch.sort.clone()));
+                        successors.push((
+                            Step::Send {
+                                from: from.clone(),
+                                to: peer.clone(),
+                                label: branch.label.clone(),
+                                sort: branch.sort.clone(),
+                            },
+                            successor,
+                        ));
+                    }
+                }
+                (Local::Offer { peer, branches }, Semantics::Asynchronous { .. }) => {
+                    let Some(q) = self.role(peer) else { continue };
+                    let Some((label, sort)) = state.queues[q * n + p].front() else {
+                        continue;
+                    };
+                    match branches.iter().find(|b| b.label == *label) {
+                        Some(branch) if branch.sort == *sort => {
+                            let mut successor = state.clone();
+                            successor.locals[p] = branch.continuation.unfold();
+                            successor.queues[q * n + p].pop_front();
+                            successors.push((
+                                Step::Receive {
+                                    from: peer.clone(),
+                                    to: from.clone(),
+                                    label: label.clone(),
+                                    sort: sort.clone(),
+                                },
+                                successor,
+                            ));
+                        }
+                        Some(branch) => errors.push(format!(
+                            "{from} receives {label}({sort}) from {peer}, but expects {}({})",
+                            branch.label, branch.sort
+                        )),
+                        None => errors.push(format!(
+                            "{from} receives {label}({sort}) from {peer}, which it does not offer"
+                        )),
+                    }
+                }
+                _ => {}
+            }
+        }
+        (successors, errors, blocked)
+    }
+
+    /// Why no participant can move in `state`.
+    fn stuck(&self, state: &State) -> String {
+        let n = self.roles.len();
+        let mut reasons = Vec::new();
+        for (p, local) in state.locals.iter().enumerate() {
+            let role = &self.roles[p];
+            match local {
+                Local::Select { peer, .. } => {
+                    reasons.push(format!("{role} waits to send to {peer}"))
+                }
+                Local::Offer { peer, .. } => reasons.push(format!("{role} waits for {peer}")),
+                Local::End | Local::Rec(..) => {}
+                Local::Var(variable) => {
+                    reasons.push(format!("{role} jumps to unbound variable {variable}"));
+                }
+            }
+        }
+        for (i, queue) in state.queues.iter().enumerate() {
+            if let Some((label, sort)) = queue.front() {
+                reasons.push(format!(
+                    "{label}({sort}) from {} to {} is never received",
+                    self.roles[i / n],
+                    self.roles[i % n]
+                ));
+            }
+        }
+        reasons.join(", ")
+    }
+
+    fn trace(&self, mut state: usize) -> Vec<Step> {
+        let mut trace = Vec::new();
+        while let Some((parent, step)) = &self.parents[state] {
+            trace.push(step.clone());
+            state = *parent;
+        }
+        trace.reverse();
+        trace
+    }
+
+    fn counterexample(&self, found: Option<&(usize, String)>) -> Verdict {
+        match found {
+            Some((state, reason)) => Verdict::Violated(Counterexample {
+                reason: reason.clone(),
+                trace: self.trace(*state),
+                cycle: Vec::new(),
+            }),
+            None => Verdict::Holds,
+        }
+    }
+
+    /// A reachable cycle, found depth first: the state it starts from and its steps.
+    fn cycle(&self) -> Option<(usize, Vec<Step>)> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Colour {
+            White,
+            Grey,
+            Black,
+        }
+        let mut colour = vec![Colour::White; self.states.len()];
+        // The path from the initial state: each state with the index of its next edge to follow.
+        let mut path: Vec<(usize, usize)> = vec![(0, 0)];
+        colour[0] = Colour::Grey;
+        while let Some(&(state, edge)) = path.last() {
+            let Some((_, target)) = self.edges[state].get(edge) else {
+                colour[state] = Colour::Black;
+                path.pop();
+                continue;
+            };
+            path.last_mut().expect("path is not empty").1 += 1;
+            match colour[*target] {
+                Colour::White => {
+                    colour[*target] = Colour::Grey;
+                    path.push((*target, 0));
+                }
+                Colour::Grey => {
+                    let start = path.iter().position(|(s, _)| s == target)?;
+                    let steps = path[start..]
+                        .iter()
+                        .map(|&(s, next)| self.edges[s][next - 1].0.clone())
+                        .collect();
+                    return Some((*target, steps));
+                }
+                Colour::Black => {}
+            }
+        }
+        None
+    }
+
+    fn report(&self, session: &str) -> Report {
+        let termination = match self.cycle() {
+            Some((state, cycle)) => Verdict::Violated(Counterexample {
+                reason: "the run can repeat forever".to_string(),
+                trace: self.trace(state),
+                cycle,
+            }),
+            None => Verdict::Holds,
+        };
+        Report {
+            session: session.to_string(),
+            semantics: self.semantics,
+            states: self.states.len(),
+            truncated: self.truncated,
+            safety: self.counterexample(self.unsafe_state.as_ref()),
+            deadlock_freedom: self.counterexample(self.deadlock.as_ref()),
+            termination,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Report, Semantics, Step, Verdict};
+    use crate::ctx::Context;
+
+    const ASYNCHRONOUS: Semantics = Semantics::Asynchronous { bound: 2 };
+
+    fn report(source: &str, semantics: Semantics) -> Report {
+        let mut reports = check(&Context::parse(source).unwrap(), semantics);
+        assert_eq!(reports.len(), 1);
+        reports.remove(0)
+    }
+
+    fn communicate(from: &str, to: &str, label: &str, sort: &str) -> Step {
+        Step::Communicate {
+            from: from.to_string(),
+            to: to.to_string(),
+            label: label.to_string(),
+            sort: sort.to_string(),
+        }
+    }
+
+    #[test]
+    fn a_dual_pair_satisfies_every_property() {
+        let source = "s[a]: b<+>{ping(Ping).b&pong(Pong).end, quit(Quit).end},
+            s[b]: a&{ping(Ping).a<+>pong(Pong).end, quit(Quit).end}";
+        for semantics in [Semantics::Synchronous, ASYNCHRONOUS] {
+            let report = report(source, semantics);
+            assert_eq!(report.session, "s");
+            assert!(!report.truncated);
+            assert_eq!(report.safety, Verdict::Holds);
+            assert_eq!(report.deadlock_freedom, Verdict::Holds);
+            assert_eq!(report.termination, Verdict::Holds);
+        }
+        // the initial state, the one after `ping` and the one where both reached `end`
+        assert_eq!(report(source, Semantics::Synchronous).states, 3);
+    }
+
+    #[test]
+    fn a_pair_that_waits_for_each_other_deadlocks() {
+        let source = "s[a]: b<+>hello(Hello).b&x(X).end, s[b]: a&hello(Hello).a&y(Y).end";
+        for semantics in [Semantics::Synchronous, ASYNCHRONOUS] {
+            let report = report(source, semantics);
+            assert!(report.safety.holds());
+            assert!(report.termination.holds());
+            let Verdict::Violated(counterexample) = report.deadlock_freedom else {
+                panic!("the deadlock was not found under {semantics}");
+            };
+            assert_eq!(counterexample.reason, "a waits for b, b waits for a");
+            assert!(counterexample.cycle.is_empty());
+        }
+        let Verdict::Violated(counterexample) =
+            report(source, Semantics::Synchronous).deadlock_freedom
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            counterexample.trace,
+            [communicate("a", "b", "hello", "Hello")]
+        );
+    }
+
+    #[test]
+    fn sends_that_cross_deadlock_only_without_queues() {
+        let source = "s[a]: b<+>x(X).b&y(Y).end, s[b]: a<+>y(Y).a&x(X).end";
+        let Verdict::Violated(counterexample) =
+            report(source, Semantics::Synchronous).deadlock_freedom
+        else {
+            panic!("both roles wait to send, which cannot happen at once");
+        };
+        assert_eq!(
+            counterexample.reason,
+            "a waits to send to b, b waits to send to a"
+        );
+        assert!(counterexample.trace.is_empty());
+        assert!(report(source, ASYNCHRONOUS).deadlock_freedom.holds());
+    }
+
+    #[test]
+    fn a_message_of_another_sort_is_unsafe() {
+        let source = "s[a]: b<+>m(X).end, s[b]: a&m(Y).end";
+        let Verdict::Violated(counterexample) = report(source, Semantics::Synchronous).safety
+        else {
+            panic!("the sort of the message was not checked");
+        };
+        assert_eq!(
+            counterexample.reason,
+            "a sends m(X) to b, which expects m(Y)"
+        );
+        assert!(counterexample.trace.is_empty());
+
+        let Verdict::Violated(counterexample) = report(source, ASYNCHRONOUS).safety else {
+            panic!("the sort of the message was not checked");
+        };
+        assert_eq!(
+            counterexample.reason,
+            "b receives m(X) from a, but expects m(Y)"
+        );
+        assert_eq!(counterexample.trace.len(), 1);
+        assert_eq!(counterexample.trace[0].to_string(), "a!b:m(X)");
+    }
+
+    #[test]
+    fn a_loop_without_an_exit_does_not_terminate() {
+        let source = "s[a]: mu(t)(b<+>ping(Ping).t), s[b]: mu(t)(a&ping(Ping).t)";
+        let report = report(source, Semantics::Synchronous);
+        assert!(report.safety.holds());
+        assert!(report.deadlock_freedom.holds());
+        let Verdict::Violated(counterexample) = report.termination else {
+            panic!("the loop was taken for a finite run");
+        };
+        assert!(counterexample.trace.is_empty());
+        assert_eq!(
+            counterexample.cycle,
+            [communicate("a", "b", "ping", "Ping")]
+        );
+    }
+
+    #[test]
+    fn a_full_queue_truncates_the_state_space() {
+        let source = "s[a]: mu(t)(b<+>ping(Ping).t), s[b]: mu(t)(a&ping(Ping).t)";
+        assert!(!report(source, Semantics::Synchronous).truncated);
+        assert!(report(source, ASYNCHRONOUS).truncated);
+    }
+}
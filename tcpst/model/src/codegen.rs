@@ -0,0 +1,146 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::{ctx::Context, local::Local};
+
+// The functions of this module return Rust source meant to be written out by a build script
+// and `include!`d. The source refers to the library as `::tcp_st`.
+
+/// The declaration of `name` as the session type of `local` with `tcp_st::session_type!`.
+/// The roles and payload sorts of `local` must be in scope where it is included.
+pub fn session_type(name: &str, local: &Local) -> String {
+    format!("::tcp_st::session_type! {{\n    pub type {name} = {local};\n}}\n")
+}
+
+/// Like [session_type], and also declare `dual` as the dual of `local`, which is
+/// the view of `role` and must only have actions with a single peer.
+pub fn session_type_with_dual(name: &str, local: &Local, dual: &str, role: &str) -> String {
+    format!(
+        "::tcp_st::session_type! {{\n    pub type {name} = {local};\n    dual pub type {dual} for {role};\n}}\n"
+    )
+}
+
+/// A message struct for every payload sort of `context`, and a registry of them.
+/// `origin` is the name of the file the context was read from, used in the documentation.
+///
+/// The sorts in `provided` already have a hand-written message, given as the path of its type,
+/// which is imported under the name of the sort instead, e.g. the validated segments of
+/// `tcp_st::net_channel`. Any other struct holds the packet of the message as is and
+/// implements `tcp_st::Message` without validating it, as a stub for a sort without
+/// a real representation yet. The registry is made of:
+///
+/// * `LABELS`, every label of the context with the sort of its payload,
+/// * `Payload`, an enum with a variant per sort, whose `Payload::decode` decodes
+///   a packet into the message of a sort given by name.
+pub fn messages(context: &Context, origin: &str, provided: &[(&str, &str)]) -> String {
+    let sorts = context.sorts();
+    let mut labels = BTreeSet::new();
+    for entry in &context.entries {
+        entry.local.visit(&mut |branch| {
+            labels.insert((branch.label.name.as_str(), branch.sort.name.as_str()));
+        });
+    }
+
+    let mut source = String::new();
+    for sort in &sorts {
+        if let Some((_, path)) = provided.iter().find(|(name, _)| name == sort) {
+            let _ = write!(
+                source,
+                "/// The payload sort `{sort}` of `{origin}`.
+pub use {path} as {sort};
+
+"
+            );
+            continue;
+        }
+        let _ = write!(
+            source,
+            "/// The payload sort `{sort}` of `{origin}`.
+pub struct {sort} {{
+    pub packet: Vec<u8>,
+}}
+
+impl ::tcp_st::Message for {sort} {{
+    fn to_net_representation(self) -> Vec<u8> {{
+        self.packet
+    }}
+
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, ::tcp_st::DecodeError> {{
+        Ok({sort} {{ packet }})
+    }}
+}}
+
+"
+        );
+    }
+
+    let _ = writeln!(
+        source,
+        "/// The labels of `{origin}` with the payload sort each of them carries."
+    );
+    let _ = writeln!(source, "pub const LABELS: &[(&str, &str)] = &[");
+    for (label, sort) in &labels {
+        let _ = writeln!(source, "    (\"{label}\", \"{sort}\"),");
+    }
+    let _ = writeln!(source, "];\n");
+
+    let _ = writeln!(
+        source,
+        "/// [Payload] is the message of any payload sort of `{origin}`."
+    );
+    let _ = writeln!(source, "pub enum Payload {{");
+    for sort in &sorts {
+        let _ = writeln!(source, "    {sort}({sort}),");
+    }
+    let _ = writeln!(source, "}}\n");
+    let _ = writeln!(source, "impl Payload {{");
+    let _ = writeln!(
+        source,
+        "    /// The names of the payload sorts, in alphabetical order."
+    );
+    let _ = writeln!(source, "    pub const SORTS: &'static [&'static str] = &[");
+    for sort in &sorts {
+        let _ = writeln!(source, "        \"{sort}\",");
+    }
+    let _ = writeln!(source, "    ];\n");
+    let _ = writeln!(
+        source,
+        "    /// The name of the payload sort of the message."
+    );
+    let _ = writeln!(source, "    pub fn sort(&self) -> &'static str {{");
+    let _ = writeln!(source, "        match self {{");
+    for sort in &sorts {
+        let _ = writeln!(source, "            Payload::{sort}(_) => \"{sort}\",");
+    }
+    let _ = writeln!(source, "        }}\n    }}\n");
+    let _ = writeln!(
+        source,
+        "    /// Decode `packet` as the message of the payload sort named `sort`.
+    /// Returns [None] if there is no such sort."
+    );
+    let _ = writeln!(
+        source,
+        "    pub fn decode(sort: &str, packet: Vec<u8>) -> Option<Result<Payload, ::tcp_st::DecodeError>> {{"
+    );
+    let _ = writeln!(source, "        match sort {{");
+    for sort in &sorts {
+        let _ = writeln!(
+            source,
+            "            \"{sort}\" => Some(<{sort} as ::tcp_st::Message>::from_net_representation(packet).map(Payload::{sort})),"
+        );
+    }
+    let _ = writeln!(source, "            _ => None,\n        }}\n    }}\n}}");
+    source
+}
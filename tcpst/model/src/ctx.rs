@@ -0,0 +1,444 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::collections::BTreeSet;
+
+use crate::{
+    lexer::{Cursor, Name, ParseError, Span, Token},
+    local::{self, Local},
+};
+
+/// [Context] is a typing context in the syntax of `tcp.ctx` and `pop3.ctx`:
+/// a comma separated list of entries `s[role]: <local type>`, one per role of session `s`.
+///
+/// Parsing checks that the context is well-formed: every recursion variable is bound
+/// by an enclosing `mu`, no choice has two branches with the same label, no role
+/// interacts with itself, and no role has two entries in the same session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    pub entries: Vec<Entry>,
+}
+
+/// [Entry] is the local type `local` of `role` in session `session`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub session: Name,
+    pub role: Name,
+    pub local: Type,
+    pub span: Span,
+}
+
+/// [Type] is a local type of a [Context], with the spans of its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// `end`
+    End(Span),
+    /// `t`, a jump back to the loop bound by `mu(t)`.
+    Var(Name),
+    /// `mu(t)(...)`
+    Rec {
+        variable: Name,
+        body: Box<Type>,
+        span: Span,
+    },
+    /// `peer<+>{...}`
+    Select {
+        peer: Name,
+        branches: Vec<Branch>,
+        span: Span,
+    },
+    /// `peer&{...}`
+    Offer {
+        peer: Name,
+        branches: Vec<Branch>,
+        span: Span,
+    },
+}
+
+/// [Branch] is one `label(Sort).continuation` of a choice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub label: Name,
+    pub sort: Name,
+    pub continuation: Type,
+    pub span: Span,
+}
+
+impl Context {
+    /// Parse a context.
+    pub fn parse(source: &str) -> Result<Context, ParseError> {
+        let mut parser = Parser {
+            cursor: Cursor::new(source)?,
+            role: String::new(),
+            variables: Vec::new(),
+        };
+        let context = parser.context()?;
+        parser.cursor.finish()?;
+        Ok(context)
+    }
+
+    /// The entry of `role`, if any.
+    pub fn entry(&self, role: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.role.name == role)
+    }
+
+    /// The roles with an entry, in order of appearance.
+    pub fn roles(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.role.name.as_str())
+    }
+
+    /// The payload sorts used anywhere in the context, in alphabetical order.
+    pub fn sorts(&self) -> BTreeSet<&str> {
+        let mut sorts = BTreeSet::new();
+        for entry in &self.entries {
+            entry.local.visit(&mut |branch| {
+                sorts.insert(branch.sort.name.as_str());
+            });
+        }
+        sorts
+    }
+}
+
+impl Type {
+    /// Parse a local type on its own, as in the right-hand side of an entry,
+    /// with the same checks as [Context::parse].
+    pub fn parse(source: &str) -> Result<Type, ParseError> {
+        let mut parser = Parser {
+            cursor: Cursor::new(source)?,
+            role: String::new(),
+            variables: Vec::new(),
+        };
+        let local = parser.local()?;
+        parser.cursor.finish()?;
+        Ok(local)
+    }
+
+    /// The span of the whole type.
+    pub fn span(&self) -> Span {
+        match self {
+            Type::End(span)
+            | Type::Rec { span, .. }
+            | Type::Select { span, .. }
+            | Type::Offer { span, .. } => *span,
+            Type::Var(variable) => variable.span,
+        }
+    }
+
+    /// Call `f` with every branch of the type, outermost first.
+    pub fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Branch)) {
+        match self {
+            Type::End(_) | Type::Var(_) => {}
+            Type::Rec { body, .. } => body.visit(f),
+            Type::Select { branches, .. } | Type::Offer { branches, .. } => {
+                for branch in branches {
+                    f(branch);
+                    branch.continuation.visit(f);
+                }
+            }
+        }
+    }
+
+    /// The type without spans, as used for projection and code generation.
+    pub fn to_local(&self) -> Local {
+        let branches = |branches: &[Branch]| {
+            branches
+                .iter()
+                .map(|branch| local::Branch {
+                    label: branch.label.name.clone(),
+                    sort: branch.sort.name.clone(),
+                    continuation: branch.continuation.to_local(),
+                })
+                .collect()
+        };
+        match self {
+            Type::End(_) => Local::End,
+            Type::Var(variable) => Local::Var(variable.name.clone()),
+            Type::Rec { variable, body, .. } => {
+                Local::Rec(variable.name.clone(), Box::new(body.to_local()))
+            }
+            Type::Select {
+                peer, branches: b, ..
+            } => Local::Select {
+                peer: peer.name.clone(),
+                branches: branches(b),
+            },
+            Type::Offer {
+                peer, branches: b, ..
+            } => Local::Offer {
+                peer: peer.name.clone(),
+                branches: branches(b),
+            },
+        }
+    }
+}
+
+struct Parser {
+    cursor: Cursor,
+    /// The role of the entry being parsed.
+    role: String,
+    /// The recursion variables bound at the current point, innermost last.
+    variables: Vec<String>,
+}
+
+impl Parser {
+    fn context(&mut self) -> Result<Context, ParseError> {
+        let mut entries: Vec<Entry> = vec![self.entry()?];
+        while self.cursor.is_at(&Token::Punct(',')) {
+            self.cursor.expect(&Token::Punct(','))?;
+            if self.cursor.peek().is_none() {
+                break;
+            }
+            let entry = self.entry()?;
+            if entries
+                .iter()
+                .any(|e| e.session.name == entry.session.name && e.role.name == entry.role.name)
+            {
+                return Err(ParseError::new(
+                    entry.role.span,
+                    format!(
+                        "`{}` already has an entry in session `{}`",
+                        entry.role, entry.session
+                    ),
+                ));
+            }
+            entries.push(entry);
+        }
+        Ok(Context { entries })
+    }
+
+    fn entry(&mut self) -> Result<Entry, ParseError> {
+        let session = self.cursor.ident("a session name")?;
+        self.cursor.expect(&Token::Punct('['))?;
+        let role = self.cursor.ident("a role")?;
+        self.cursor.expect(&Token::Punct(']'))?;
+        self.cursor.expect(&Token::Punct(':'))?;
+        self.role = role.name.clone();
+        let local = self.local()?;
+        Ok(Entry {
+            span: session.span.to(local.span()),
+            session,
+            role,
+            local,
+        })
+    }
+
+    fn local(&mut self) -> Result<Type, ParseError> {
+        let name = self
+            .cursor
+            .ident("a role, `mu`, `end` or a recursion variable")?;
+        if name.name == "end" {
+            return Ok(Type::End(name.span));
+        }
+        if name.name == "mu" && self.cursor.is_at(&Token::Punct('(')) {
+            self.cursor.expect(&Token::Punct('('))?;
+            let variable = self.cursor.ident("a recursion variable")?;
+            self.cursor.expect(&Token::Punct(')'))?;
+            self.cursor.expect(&Token::Punct('('))?;
+            self.variables.push(variable.name.clone());
+            let body = self.local()?;
+            self.variables.pop();
+            let close = self.cursor.expect(&Token::Punct(')'))?;
+            return Ok(Type::Rec {
+                variable,
+                body: Box::new(body),
+                span: name.span.to(close),
+            });
+        }
+        let select = self.cursor.is_at(&Token::Select);
+        if !select && !self.cursor.is_at(&Token::Offer) {
+            if !self.variables.contains(&name.name) {
+                return Err(ParseError::new(
+                    name.span,
+                    format!("recursion variable `{name}` is not bound by any `mu`"),
+                ));
+            }
+            return Ok(Type::Var(name));
+        }
+        if name.name == self.role {
+            return Err(ParseError::new(
+                name.span,
+                format!("`{name}` interacts with itself"),
+            ));
+        }
+        self.cursor.expect(if select {
+            &Token::Select
+        } else {
+            &Token::Offer
+        })?;
+        let branches = self.branches()?;
+        let span = name.span.to(self.cursor.last());
+        Ok(if select {
+            Type::Select {
+                peer: name,
+                branches,
+                span,
+            }
+        } else {
+            Type::Offer {
+                peer: name,
+                branches,
+                span,
+            }
+        })
+    }
+
+    /// Parse either a single branch or a `{...}` block of comma separated branches.
+    fn branches(&mut self) -> Result<Vec<Branch>, ParseError> {
+        if !self.cursor.is_at(&Token::Punct('{')) {
+            return Ok(vec![self.branch()?]);
+        }
+        self.cursor.expect(&Token::Punct('{'))?;
+        let mut branches: Vec<Branch> = vec![self.branch()?];
+        while self.cursor.is_at(&Token::Punct(',')) {
+            self.cursor.expect(&Token::Punct(','))?;
+            if self.cursor.is_at(&Token::Punct('}')) {
+                break;
+            }
+            let branch = self.branch()?;
+            if branches.iter().any(|b| b.label.name == branch.label.name) {
+                return Err(ParseError::new(
+                    branch.label.span,
+                    format!("label `{}` appears twice in the same choice", branch.label),
+                ));
+            }
+            branches.push(branch);
+        }
+        self.cursor.expect(&Token::Punct('}'))?;
+        Ok(branches)
+    }
+
+    fn branch(&mut self) -> Result<Branch, ParseError> {
+        let label = self.cursor.ident("a label")?;
+        self.cursor.expect(&Token::Punct('('))?;
+        let sort = self.cursor.ident("a payload sort")?;
+        self.cursor.expect(&Token::Punct(')'))?;
+        self.cursor.expect(&Token::Punct('.'))?;
+        let continuation = self.local()?;
+        Ok(Branch {
+            span: label.span.to(continuation.span()),
+            label,
+            sort,
+            continuation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{Context, Type};
+
+    #[test]
+    fn the_models_of_the_repository_parse() {
+        let tcp = Context::parse(include_str!("../../../tcp.ctx")).unwrap();
+        assert_eq!(
+            tcp.roles().collect::<Vec<_>>(),
+            [
+                "server_user",
+                "server_system",
+                "client_system",
+                "client_user"
+            ]
+        );
+        let sorts = tcp.sorts();
+        for sort in [
+            "SegSynSet",
+            "SegSynAckSet",
+            "SegAckSet",
+            "SegFinSet",
+            "SegRstSet",
+            "Data",
+        ] {
+            assert!(sorts.contains(sort), "tcp.ctx does not use {sort}");
+        }
+
+        let pop3 = Context::parse(include_str!("../../../pop3.ctx")).unwrap();
+        assert_eq!(pop3.roles().collect::<Vec<_>>(), ["server", "client"]);
+        // the client offers what the server selects, with the same sorts
+        let sorts = |role: &str| {
+            let mut sorts = BTreeSet::new();
+            pop3.entry(role).unwrap().local.visit(&mut |branch| {
+                sorts.insert(branch.sort.name.clone());
+            });
+            sorts
+        };
+        assert_eq!(sorts("server"), sorts("client"));
+    }
+
+    #[test]
+    fn spans_cover_their_part_of_the_source() {
+        let source = "s[user]: system<+>{open(Open).end, close(Close).end}";
+        let context = Context::parse(source).unwrap();
+        let entry = context.entry("user").unwrap();
+        assert_eq!(entry.span.text(source), source);
+        assert_eq!(
+            entry.local.span().text(source),
+            "system<+>{open(Open).end, close(Close).end}"
+        );
+        let mut branches = Vec::new();
+        entry
+            .local
+            .visit(&mut |branch| branches.push(branch.span.text(source)));
+        assert_eq!(branches, ["open(Open).end", "close(Close).end"]);
+    }
+
+    #[test]
+    fn an_unbound_variable_is_an_error_at_the_variable() {
+        let source = "s[user]: mu(t)(system<+>{data(Data).t, close(Close).s})";
+        let error = Context::parse(source).unwrap_err();
+        assert_eq!(error.span.text(source), "s");
+        assert_eq!(error.span.start.column, 53);
+        assert_eq!(
+            error.message,
+            "recursion variable `s` is not bound by any `mu`"
+        );
+        // a variable is only bound inside its `mu`
+        let error =
+            Type::parse("system<+>{data(Data).mu(t)(system<+>ok(Ok).t), again(Ok).t}").unwrap_err();
+        assert_eq!(error.span.start.column, 58);
+    }
+
+    #[test]
+    fn a_duplicate_label_is_an_error_at_its_second_occurrence() {
+        let source = "s[user]:\n  system&{data(Data).end,\n    data(Close).end}";
+        let error = Context::parse(source).unwrap_err();
+        assert_eq!(error.span.text(source), "data");
+        assert_eq!((error.span.start.line, error.span.start.column), (3, 5));
+        assert_eq!(
+            error.to_string(),
+            "3:5: label `data` appears twice in the same choice"
+        );
+    }
+
+    #[test]
+    fn an_unknown_token_fails_the_parse() {
+        let source = "s[user]: system<+>open(Open)!end";
+        let error = Context::parse(source).unwrap_err();
+        assert_eq!(error.span.text(source), "!");
+        assert_eq!(error.message, "unexpected `!`");
+    }
+
+    #[test]
+    fn roles_must_be_distinct() {
+        let source = "s[user]: user<+>open(Open).end";
+        let error = Context::parse(source).unwrap_err();
+        assert_eq!(error.span.start.column, 10);
+        assert_eq!(error.message, "`user` interacts with itself");
+
+        let source = "s[user]: system<+>open(Open).end, s[user]: system&open(Open).end";
+        let error = Context::parse(source).unwrap_err();
+        assert_eq!(error.span.start.column, 37);
+        assert_eq!(error.message, "`user` already has an entry in session `s`");
+    }
+}
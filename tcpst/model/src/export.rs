@@ -0,0 +1,372 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{collections::BTreeSet, fmt, fmt::Write};
+
+use crate::{ctx::Context, local::Local};
+
+/// [Direction] of a [Transition], from the point of view of the role of the automaton.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// [Transition] sends or receives `label(sort)` to or from `peer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub source: usize,
+    pub target: usize,
+    pub direction: Direction,
+    pub peer: String,
+    pub label: String,
+    pub sort: String,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Send => '!',
+            Direction::Receive => '?',
+        };
+        write!(f, "{}{direction}{}({})", self.peer, self.label, self.sort)
+    }
+}
+
+/// [Automaton] is the communicating automaton of one role: a state per point of its local type,
+/// starting from state `0`, with a transition per action. A loop is a cycle back to the
+/// state of its `mu`, and the states where the local type reaches `end` are final.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Automaton {
+    pub role: String,
+    pub states: usize,
+    pub finals: Vec<usize>,
+    pub transitions: Vec<Transition>,
+}
+
+impl Automaton {
+    pub fn new(role: &str, local: &Local) -> Automaton {
+        let mut automaton = Automaton {
+            role: role.to_string(),
+            states: 1,
+            finals: Vec::new(),
+            transitions: Vec::new(),
+        };
+        automaton.build(local, 0, &mut Vec::new());
+        automaton
+    }
+
+    /// The automata of every entry of `context`.
+    pub fn of_context(context: &Context) -> Vec<Automaton> {
+        context
+            .entries
+            .iter()
+            .map(|entry| Automaton::new(&entry.role.name, &entry.local.to_local()))
+            .collect()
+    }
+
+    /// Add the transitions of `local` from `state`, where `loops` maps the
+    /// recursion variables in scope to the states of their `mu`.
+    fn build(&mut self, local: &Local, state: usize, loops: &mut Vec<(String, usize)>) {
+        match local {
+            Local::End => self.finals.push(state),
+            // An unguarded jump does not communicate, the state stays without transitions.
+            Local::Var(_) => {}
+            Local::Rec(variable, body) => {
+                loops.push((variable.clone(), state));
+                self.build(body, state, loops);
+                loops.pop();
+            }
+            Local::Select { peer, branches } | Local::Offer { peer, branches } => {
+                let direction = match local {
+                    Local::Select { .. } => Direction::Send,
+                    _ => Direction::Receive,
+                };
+                for branch in branches {
+                    let jump = match &branch.continuation {
+                        Local::Var(variable) => loops
+                            .iter()
+                            .rev()
+                            .find(|(bound, _)| bound == variable)
+                            .map(|(_, state)| *state),
+                        _ => None,
+                    };
+                    let target = jump.unwrap_or_else(|| {
+                        self.states += 1;
+                        self.states - 1
+                    });
+                    self.transitions.push(Transition {
+                        source: state,
+                        target,
+                        direction,
+                        peer: peer.clone(),
+                        label: branch.label.clone(),
+                        sort: branch.sort.clone(),
+                    });
+                    if jump.is_none() {
+                        self.build(&branch.continuation, target, loops);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The automata as a Graphviz DOT graph, with a cluster per role.
+pub fn dot(automata: &[Automaton]) -> String {
+    let mut dot = String::from("digraph automata {\n    rankdir=LR;\n");
+    for automaton in automata {
+        let role = &automaton.role;
+        let _ = writeln!(
+            dot,
+            "    subgraph cluster_{role} {{\n        label=\"{role}\";"
+        );
+        let _ = writeln!(dot, "        {role}_start [shape=point];");
+        for state in 0..automaton.states {
+            let shape = if automaton.finals.contains(&state) {
+                "doublecircle"
+            } else {
+                "circle"
+            };
+            let _ = writeln!(
+                dot,
+                "        {role}_{state} [label=\"{state}\", shape={shape}];"
+            );
+        }
+        let _ = writeln!(dot, "        {role}_start -> {role}_0;");
+        for transition in &automaton.transitions {
+            let _ = writeln!(
+                dot,
+                "        {role}_{} -> {role}_{} [label=\"{transition}\"];",
+                transition.source, transition.target
+            );
+        }
+        let _ = writeln!(dot, "    }}");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The automata as a Mermaid state diagram, with a composite state per role.
+pub fn mermaid(automata: &[Automaton]) -> String {
+    let mut mermaid = String::from("stateDiagram-v2\n");
+    for automaton in automata {
+        let role = &automaton.role;
+        let _ = writeln!(mermaid, "    state {role} {{");
+        let _ = writeln!(mermaid, "        [*] --> {role}_0");
+        for transition in &automaton.transitions {
+            let _ = writeln!(
+                mermaid,
+                "        {role}_{} --> {role}_{} : {transition}",
+                transition.source, transition.target
+            );
+        }
+        for state in &automaton.finals {
+            let _ = writeln!(mermaid, "        {role}_{state} --> [*]");
+        }
+        let _ = writeln!(mermaid, "    }}");
+    }
+    mermaid
+}
+
+/// The automata as an mCRL2 process specification in the style of mpstk:
+/// sorts of the roles, labels and payloads, a process per state of every automaton,
+/// and the synchronous parallel composition of the initial states.
+/// Sending `l(S)` from `p` to `q` is `o(p, q, l, S)`, receiving it is `i(p, q, l, S)`,
+/// and the two communicate into `t(p, q, l, S)`. A role that reaches `end` performs `done(p)`.
+/// Identifiers are prefixed, `r_` for roles, `l_` for labels and `s_` for payload sorts,
+/// so that names from the model do not clash with mCRL2 keywords. A sort without any names,
+/// e.g. the labels of a model that only ends, is declared without constructors, as mCRL2
+/// has no empty `struct`.
+pub fn mcrl2(automata: &[Automaton]) -> String {
+    let mut roles = BTreeSet::new();
+    let mut labels = BTreeSet::new();
+    let mut sorts = BTreeSet::new();
+    for automaton in automata {
+        roles.insert(automaton.role.as_str());
+        for transition in &automaton.transitions {
+            roles.insert(transition.peer.as_str());
+            labels.insert(transition.label.as_str());
+            sorts.insert(transition.sort.as_str());
+        }
+    }
+    let declaration = |sort: &str, prefix: &str, names: &BTreeSet<&str>| {
+        if names.is_empty() {
+            return format!("    {sort};");
+        }
+        let constructors = names
+            .iter()
+            .map(|name| format!("{prefix}{name}"))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        format!("    {sort} = struct {constructors};")
+    };
+
+    let mut spec = String::from("sort\n");
+    let _ = writeln!(spec, "{}", declaration("Role", "r_", &roles));
+    let _ = writeln!(spec, "{}", declaration("Label", "l_", &labels));
+    let _ = writeln!(spec, "{}", declaration("Payload", "s_", &sorts));
+    spec.push_str("\nact\n    o, i, t: Role # Role # Label # Payload;\n    done: Role;\n\nproc\n");
+    for automaton in automata {
+        let role = &automaton.role;
+        for state in 0..automaton.states {
+            let mut summands = Vec::new();
+            for transition in automaton.transitions.iter().filter(|t| t.source == state) {
+                let (action, from, to) = match transition.direction {
+                    Direction::Send => ("o", role, &transition.peer),
+                    Direction::Receive => ("i", &transition.peer, role),
+                };
+                summands.push(format!(
+                    "{action}(r_{from}, r_{to}, l_{}, s_{}) . P_{role}_{}",
+                    transition.label, transition.sort, transition.target
+                ));
+            }
+            if automaton.finals.contains(&state) {
+                summands.push(format!("done(r_{role}) . delta"));
+            }
+            if summands.is_empty() {
+                summands.push("delta".to_string());
+            }
+            let _ = writeln!(spec, "    P_{role}_{state} = {};", summands.join(" + "));
+        }
+    }
+    let initial = automata
+        .iter()
+        .map(|automaton| format!("P_{}_0", automaton.role))
+        .collect::<Vec<_>>()
+        .join(" || ");
+    let _ = writeln!(
+        spec,
+        "\ninit\n    allow({{t, done}}, comm({{o | i -> t}}, {initial}));"
+    );
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dot, mcrl2, mermaid, Automaton, Direction, Transition};
+    use crate::ctx::Context;
+
+    fn automata(source: &str) -> Vec<Automaton> {
+        Automaton::of_context(&Context::parse(source).unwrap())
+    }
+
+    const PING: &str = "s[a]: mu(t)(b<+>{ping(Ping).t, quit(Quit).end}),
+        s[b]: mu(t)(a&{ping(Ping).t, quit(Quit).end})";
+
+    #[test]
+    fn a_loop_is_a_cycle_back_to_its_state() {
+        let automata = automata(PING);
+        assert_eq!(automata[0].states, 2);
+        assert_eq!(automata[0].finals, [1]);
+        assert_eq!(
+            automata[0].transitions,
+            [
+                Transition {
+                    source: 0,
+                    target: 0,
+                    direction: Direction::Send,
+                    peer: "b".to_string(),
+                    label: "ping".to_string(),
+                    sort: "Ping".to_string(),
+                },
+                Transition {
+                    source: 0,
+                    target: 1,
+                    direction: Direction::Send,
+                    peer: "b".to_string(),
+                    label: "quit".to_string(),
+                    sort: "Quit".to_string(),
+                },
+            ]
+        );
+        assert_eq!(automata[1].transitions[0].to_string(), "a?ping(Ping)");
+    }
+
+    #[test]
+    fn dot_has_a_cluster_per_role() {
+        let expected = r#"digraph automata {
+    rankdir=LR;
+    subgraph cluster_a {
+        label="a";
+        a_start [shape=point];
+        a_0 [label="0", shape=circle];
+        a_1 [label="1", shape=doublecircle];
+        a_start -> a_0;
+        a_0 -> a_0 [label="b!ping(Ping)"];
+        a_0 -> a_1 [label="b!quit(Quit)"];
+    }
+    subgraph cluster_b {
+        label="b";
+        b_start [shape=point];
+        b_0 [label="0", shape=circle];
+        b_1 [label="1", shape=doublecircle];
+        b_start -> b_0;
+        b_0 -> b_0 [label="a?ping(Ping)"];
+        b_0 -> b_1 [label="a?quit(Quit)"];
+    }
+}
+"#;
+        assert_eq!(dot(&automata(PING)), expected);
+    }
+
+    #[test]
+    fn mermaid_has_a_composite_state_per_role() {
+        let expected = "stateDiagram-v2
+    state a {
+        [*] --> a_0
+        a_0 --> a_0 : b!ping(Ping)
+        a_0 --> a_1 : b!quit(Quit)
+        a_1 --> [*]
+    }
+    state b {
+        [*] --> b_0
+        b_0 --> b_0 : a?ping(Ping)
+        b_0 --> b_1 : a?quit(Quit)
+        b_1 --> [*]
+    }
+";
+        assert_eq!(mermaid(&automata(PING)), expected);
+    }
+
+    #[test]
+    fn mcrl2_has_a_process_per_state() {
+        let expected = "sort
+    Role = struct r_a | r_b;
+    Label = struct l_ping | l_quit;
+    Payload = struct s_Ping | s_Quit;
+
+act
+    o, i, t: Role # Role # Label # Payload;
+    done: Role;
+
+proc
+    P_a_0 = o(r_a, r_b, l_ping, s_Ping) . P_a_0 + o(r_a, r_b, l_quit, s_Quit) . P_a_1;
+    P_a_1 = done(r_a) . delta;
+    P_b_0 = i(r_a, r_b, l_ping, s_Ping) . P_b_0 + i(r_a, r_b, l_quit, s_Quit) . P_b_1;
+    P_b_1 = done(r_b) . delta;
+
+init
+    allow({t, done}, comm({o | i -> t}, P_a_0 || P_b_0));
+";
+        assert_eq!(mcrl2(&automata(PING)), expected);
+    }
+
+    #[test]
+    fn mcrl2_declares_sorts_without_names_without_constructors() {
+        let spec = mcrl2(&automata("s[a]: end"));
+        assert!(spec.starts_with("sort\n    Role = struct r_a;\n    Label;\n    Payload;\n"));
+        assert!(!spec.contains("struct ;"));
+        assert!(spec.contains("    P_a_0 = done(r_a) . delta;\n"));
+    }
+}
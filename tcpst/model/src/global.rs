@@ -0,0 +1,402 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    lexer::{Cursor, ParseError, Span, Token},
+    local::{Branch, Local},
+    ProjectionError,
+};
+
+/// [Global] is a global protocol, the interactions between all roles:
+///
+/// ```text
+/// server_user->server_system:open(Open).
+/// server_system->server_user:{
+///     tcb_created(TcbCreated).end,
+///     error_no_room(ErrorInsufficientResources).end
+/// }
+/// ```
+///
+/// `p->q:label(Sort).G` is a message from `p` to `q`, and `p->q:{...}` a choice of `p`
+/// between comma separated branches. Recursion is `mu(t)(G)` and `t` as in `tcp.ctx`,
+/// the protocol ends with `end`, and `//` starts a comment. As in `tcp.ctx`, a recursion
+/// variable must be bound by an enclosing `mu` and the labels of a choice must differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Global {
+    End,
+    Var(String),
+    Rec(String, Box<Global>),
+    Message {
+        from: String,
+        to: String,
+        branches: Vec<GlobalBranch>,
+    },
+}
+
+/// [GlobalBranch] is one `label(Sort).continuation` of a [Global] choice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalBranch {
+    pub label: String,
+    pub sort: String,
+    pub continuation: Global,
+}
+
+impl Global {
+    /// Parse a global protocol.
+    pub fn parse(source: &str) -> Result<Global, ParseError> {
+        let mut parser = Parser {
+            cursor: Cursor::new(source)?,
+            variables: Vec::new(),
+        };
+        let global = parser.global()?;
+        parser.cursor.finish()?;
+        Ok(global)
+    }
+
+    /// The roles taking part in the protocol, in alphabetical order.
+    pub fn roles(&self) -> BTreeSet<String> {
+        let mut roles = BTreeSet::new();
+        self.collect_roles(&mut roles);
+        roles
+    }
+
+    fn collect_roles(&self, roles: &mut BTreeSet<String>) {
+        match self {
+            Global::End | Global::Var(_) => {}
+            Global::Rec(_, body) => body.collect_roles(roles),
+            Global::Message { from, to, branches } => {
+                roles.insert(from.clone());
+                roles.insert(to.clone());
+                for branch in branches {
+                    branch.continuation.collect_roles(roles);
+                }
+            }
+        }
+    }
+
+    /// Project the protocol onto `role`, giving the local type `role` must follow.
+    /// Fails if the protocol is not projectable, e.g. when a role that does not take part
+    /// in a choice behaves differently depending on the branch and cannot find out which was taken.
+    pub fn project(&self, role: &str) -> Result<Local, ProjectionError> {
+        self.project_onto(role).map_err(|message| ProjectionError {
+            role: role.to_string(),
+            message,
+        })
+    }
+
+    fn project_onto(&self, role: &str) -> Result<Local, String> {
+        match self {
+            Global::End => Ok(Local::End),
+            Global::Var(variable) => Ok(Local::Var(variable.clone())),
+            Global::Rec(variable, body) => {
+                Ok(Local::rec(variable.clone(), body.project_onto(role)?))
+            }
+            Global::Message { from, to, .. } if from == to => {
+                Err(format!("`{from}` sends a message to itself"))
+            }
+            Global::Message { from, to, branches } if from == role || to == role => {
+                let branches = branches
+                    .iter()
+                    .map(|branch| {
+                        Ok(Branch {
+                            label: branch.label.clone(),
+                            sort: branch.sort.clone(),
+                            continuation: branch.continuation.project_onto(role)?,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(if from == role {
+                    Local::Select {
+                        peer: to.clone(),
+                        branches,
+                    }
+                } else {
+                    Local::Offer {
+                        peer: from.clone(),
+                        branches,
+                    }
+                })
+            }
+            Global::Message { branches, .. } => {
+                let mut projections = branches.iter().map(|b| b.continuation.project_onto(role));
+                let mut merged = projections.next().unwrap_or(Ok(Local::End))?;
+                for projection in projections {
+                    merged = merged.merge(projection?)?;
+                }
+                Ok(merged)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Global {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Global::End => write!(f, "end"),
+            Global::Var(variable) => write!(f, "{variable}"),
+            Global::Rec(variable, body) => write!(f, "mu({variable})({body})"),
+            Global::Message { from, to, branches } => {
+                write!(f, "{from}->{to}:")?;
+                if let [branch] = branches.as_slice() {
+                    return write!(f, "{branch}");
+                }
+                write!(f, "{{")?;
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{branch}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for GlobalBranch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}).{}", self.label, self.sort, self.continuation)
+    }
+}
+
+struct Parser {
+    cursor: Cursor,
+    /// The recursion variables bound at the current point, innermost last.
+    variables: Vec<String>,
+}
+
+impl Parser {
+    fn global(&mut self) -> Result<Global, ParseError> {
+        let name = self
+            .cursor
+            .ident("a role, `mu`, `end` or a recursion variable")?;
+        if name.name == "end" {
+            return Ok(Global::End);
+        }
+        if name.name == "mu" && self.cursor.is_at(&Token::Punct('(')) {
+            self.cursor.expect(&Token::Punct('('))?;
+            let variable = self.cursor.ident("a recursion variable")?.name;
+            self.cursor.expect(&Token::Punct(')'))?;
+            self.cursor.expect(&Token::Punct('('))?;
+            self.variables.push(variable.clone());
+            let body = self.global()?;
+            self.variables.pop();
+            self.cursor.expect(&Token::Punct(')'))?;
+            return Ok(Global::Rec(variable, Box::new(body)));
+        }
+        if !self.cursor.is_at(&Token::Arrow) {
+            if !self.variables.contains(&name.name) {
+                return Err(ParseError::new(
+                    name.span,
+                    format!("recursion variable `{name}` is not bound by any `mu`"),
+                ));
+            }
+            return Ok(Global::Var(name.name));
+        }
+        self.cursor.expect(&Token::Arrow)?;
+        let to = self.cursor.ident("a role")?.name;
+        self.cursor.expect(&Token::Punct(':'))?;
+        let branches = if self.cursor.is_at(&Token::Punct('{')) {
+            self.cursor.expect(&Token::Punct('{'))?;
+            let mut branches = vec![self.branch()?];
+            while self.cursor.is_at(&Token::Punct(',')) {
+                self.cursor.expect(&Token::Punct(','))?;
+                if self.cursor.is_at(&Token::Punct('}')) {
+                    break;
+                }
+                let (label, branch) = self.labelled_branch()?;
+                if branches.iter().any(|b| b.label == branch.label) {
+                    return Err(ParseError::new(
+                        label,
+                        format!("label `{}` appears twice in the same choice", branch.label),
+                    ));
+                }
+                branches.push(branch);
+            }
+            self.cursor.expect(&Token::Punct('}'))?;
+            branches
+        } else {
+            vec![self.branch()?]
+        };
+        Ok(Global::Message {
+            from: name.name,
+            to,
+            branches,
+        })
+    }
+
+    fn branch(&mut self) -> Result<GlobalBranch, ParseError> {
+        Ok(self.labelled_branch()?.1)
+    }
+
+    /// Parse a branch, along with the span of its label.
+    fn labelled_branch(&mut self) -> Result<(Span, GlobalBranch), ParseError> {
+        let label = self.cursor.ident("a label")?;
+        self.cursor.expect(&Token::Punct('('))?;
+        let sort = self.cursor.ident("a payload sort")?.name;
+        self.cursor.expect(&Token::Punct(')'))?;
+        self.cursor.expect(&Token::Punct('.'))?;
+        let continuation = self.global()?;
+        Ok((
+            label.span,
+            GlobalBranch {
+                label: label.name,
+                sort,
+                continuation,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::Global;
+    use crate::{ctx::Context, ctx::Type, export::Automaton, local::Local};
+
+    fn local(source: &str) -> Local {
+        Type::parse(source).unwrap().to_local()
+    }
+
+    #[test]
+    fn a_choice_is_projected_onto_the_roles_that_take_part_and_merged_for_the_others() {
+        let global = Global::parse(
+            "user->system:open(Open).
+            system->net:{
+                syn(Syn).net->system:syn_ack(SynAck).system->user:connected(Connected).end,
+                rst(Rst).system->user:refused(Close).end
+            }",
+        )
+        .unwrap();
+        assert_eq!(
+            global.roles(),
+            BTreeSet::from(["net".to_string(), "system".to_string(), "user".to_string()])
+        );
+        assert_eq!(
+            global.project("system").unwrap(),
+            local(
+                "user&open(Open).net<+>{
+                    syn(Syn).net&syn_ack(SynAck).user<+>connected(Connected).end,
+                    rst(Rst).user<+>refused(Close).end
+                }"
+            )
+        );
+        // the user finds out which branch was taken from the message it receives
+        assert_eq!(
+            global.project("user").unwrap(),
+            local("system<+>open(Open).system&{connected(Connected).end, refused(Close).end}")
+        );
+    }
+
+    #[test]
+    fn a_role_that_cannot_tell_the_branches_apart_is_not_projectable() {
+        // the user would have to know which branch was taken to send its message
+        let global = Global::parse(
+            "system->net:{
+                syn(Syn).user->system:open(Open).end,
+                rst(Rst).user->system:close(Close).end
+            }",
+        )
+        .unwrap();
+        assert!(global.project("system").is_ok());
+        let error = global.project("user").unwrap_err();
+        assert_eq!(error.role, "user");
+        // or the same label, with a different sort
+        let global = Global::parse(
+            "system->net:{
+                syn(Syn).system->user:done(Connected).end,
+                rst(Rst).system->user:done(Close).end
+            }",
+        )
+        .unwrap();
+        assert!(global.project("user").is_err());
+    }
+
+    #[test]
+    fn a_recursion_variable_must_be_bound() {
+        let source = "mu(t)(user->system:data(Data).s)";
+        let error = Global::parse(source).unwrap_err();
+        assert_eq!(error.span.text(source), "s");
+        assert_eq!(
+            error.message,
+            "recursion variable `s` is not bound by any `mu`"
+        );
+        assert!(Global::parse("mu(t)(user->system:data(Data).t)").is_ok());
+    }
+
+    #[test]
+    fn the_labels_of_a_choice_must_differ() {
+        let source = "user->system:{data(Data).end, close(Close).end, data(Close).end}";
+        let error = Global::parse(source).unwrap_err();
+        assert_eq!(error.span.start.column, 49);
+        assert_eq!(error.span.text(source), "data");
+        assert_eq!(
+            error.message,
+            "label `data` appears twice in the same choice"
+        );
+    }
+
+    // The server of `tcp_handshake.global` must behave towards the client as the server of
+    // `tcp.ctx` does: its actions with `client_system` are the start of a run of the model.
+    // The actions with `server_user` are skipped, the example names them differently.
+    #[test]
+    fn the_handshake_is_a_run_of_the_server_in_tcp_ctx() {
+        let handshake = Global::parse(include_str!("../../../tcp_handshake.global"))
+            .unwrap()
+            .project("server_system")
+            .unwrap()
+            .restrict("client_system")
+            .unwrap();
+        let handshake = Automaton::new("server_system", &handshake);
+        let context = Context::parse(include_str!("../../../tcp.ctx")).unwrap();
+        let model = Automaton::new(
+            "server_system",
+            &context.entry("server_system").unwrap().local.to_local(),
+        );
+        let with_user = |mut states: BTreeSet<usize>| {
+            while let Some(next) = model.transitions.iter().find(|t| {
+                states.contains(&t.source) && t.peer == "server_user" && !states.contains(&t.target)
+            }) {
+                states.insert(next.target);
+            }
+            states
+        };
+
+        // follow the model along every transition of the handshake, from its first state
+        let mut pending = vec![(0, with_user(BTreeSet::from([0])))];
+        while let Some((state, states)) = pending.pop() {
+            for transition in handshake.transitions.iter().filter(|t| t.source == state) {
+                let next: BTreeSet<usize> = model
+                    .transitions
+                    .iter()
+                    .filter(|t| {
+                        states.contains(&t.source)
+                            && t.direction == transition.direction
+                            && t.peer == transition.peer
+                            && t.label == transition.label
+                            && t.sort == transition.sort
+                    })
+                    .map(|t| t.target)
+                    .collect();
+                assert!(
+                    !next.is_empty(),
+                    "the server in tcp.ctx cannot do {transition} in states {states:?}"
+                );
+                pending.push((transition.target, with_user(next)));
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::fmt;
+
+/// [Token] is a lexical token of the protocol syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Ident(String),
+    /// `<+>`
+    Select,
+    /// `&`
+    Offer,
+    /// `->`
+    Arrow,
+    /// One of `(){}[].,:;`
+    Punct(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{ident}`"),
+            Token::Select => write!(f, "`<+>`"),
+            Token::Offer => write!(f, "`&`"),
+            Token::Arrow => write!(f, "`->`"),
+            Token::Punct(punct) => write!(f, "`{punct}`"),
+        }
+    }
+}
+
+/// [Position] is a point in the source: a byte offset, and a line and column both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// [Span] is the part of the source from `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    /// The text of `source` covered by the span.
+    pub fn text(self, source: &str) -> &str {
+        &source[self.start.offset..self.end.offset]
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+/// [ParseError] is returned for source that is not valid, pointing at the offending part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        ParseError {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// [Name] is an identifier of the source, such as a role, label or payload sort, with its span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Split `source` into tokens, skipping whitespace and `//` comments.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut position = Position {
+        offset: 0,
+        line: 1,
+        column: 1,
+    };
+    let advance = |position: &mut Position, c: char| {
+        position.offset += c.len_utf8();
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    };
+    while let Some(&(_, c)) = chars.peek() {
+        let start = position;
+        if c.is_whitespace() {
+            chars.next();
+            advance(&mut position, c);
+            continue;
+        }
+        let token = if c.is_alphanumeric() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+                advance(&mut position, c);
+            }
+            Token::Ident(ident)
+        } else {
+            chars.next();
+            advance(&mut position, c);
+            let mut take = |position: &mut Position, expected: char| {
+                if chars.peek().map(|&(_, c)| c) == Some(expected) {
+                    chars.next();
+                    advance(position, expected);
+                    true
+                } else {
+                    false
+                }
+            };
+            match c {
+                '/' if take(&mut position, '/') => {
+                    while let Some(&(_, c)) = chars.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        chars.next();
+                        advance(&mut position, c);
+                    }
+                    continue;
+                }
+                '-' if take(&mut position, '>') => Token::Arrow,
+                '<' if take(&mut position, '+') && take(&mut position, '>') => Token::Select,
+                '&' => Token::Offer,
+                '(' | ')' | '{' | '}' | '[' | ']' | '.' | ',' | ':' | ';' => Token::Punct(c),
+                _ => {
+                    return Err(ParseError::new(
+                        Span {
+                            start,
+                            end: position,
+                        },
+                        format!("unexpected `{}`", &source[start.offset..position.offset]),
+                    ))
+                }
+            }
+        };
+        tokens.push((
+            token,
+            Span {
+                start,
+                end: position,
+            },
+        ));
+    }
+    Ok(tokens)
+}
+
+/// [Cursor] walks the tokens of a source for the parsers of the crate.
+pub(crate) struct Cursor {
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+    end: Span,
+}
+
+impl Cursor {
+    pub(crate) fn new(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let end = tokens.last().map_or(
+            Span {
+                start: Position {
+                    offset: 0,
+                    line: 1,
+                    column: 1,
+                },
+                end: Position {
+                    offset: 0,
+                    line: 1,
+                    column: 1,
+                },
+            },
+            |(_, span)| Span {
+                start: span.end,
+                end: span.end,
+            },
+        );
+        Ok(Cursor {
+            tokens,
+            next: 0,
+            end,
+        })
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    pub(crate) fn is_at(&self, token: &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    /// The span of the last token taken.
+    pub(crate) fn last(&self) -> Span {
+        self.next
+            .checked_sub(1)
+            .map_or(self.end, |last| self.tokens[last].1)
+    }
+
+    fn advance(&mut self, expected: &str) -> Result<(Token, Span), ParseError> {
+        match self.tokens.get(self.next) {
+            Some(token) => {
+                self.next += 1;
+                Ok(token.clone())
+            }
+            None => Err(ParseError::new(
+                self.end,
+                format!("unexpected end of input, expected {expected}"),
+            )),
+        }
+    }
+
+    pub(crate) fn ident(&mut self, expected: &str) -> Result<Name, ParseError> {
+        match self.advance(expected)? {
+            (Token::Ident(name), span) => Ok(Name { name, span }),
+            (token, span) => Err(ParseError::new(
+                span,
+                format!("expected {expected}, found {token}"),
+            )),
+        }
+    }
+
+    pub(crate) fn expect(&mut self, expected: &Token) -> Result<Span, ParseError> {
+        match self.advance(&expected.to_string())? {
+            (token, span) if token == *expected => Ok(span),
+            (token, span) => Err(ParseError::new(
+                span,
+                format!("expected {expected}, found {token}"),
+            )),
+        }
+    }
+
+    /// Fail unless all tokens were taken.
+    pub(crate) fn finish(&self) -> Result<(), ParseError> {
+        match self.tokens.get(self.next) {
+            Some((token, span)) => Err(ParseError::new(*span, format!("unexpected {token}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Token};
+
+    #[test]
+    fn tokens_have_the_spans_of_their_text() {
+        let source = "s[user]: // the user\n  system<+>open(Open).net&x->y";
+        let tokens = tokenize(source).unwrap();
+        let text: Vec<&str> = tokens.iter().map(|(_, span)| span.text(source)).collect();
+        assert_eq!(
+            text,
+            [
+                "s", "[", "user", "]", ":", "system", "<+>", "open", "(", "Open", ")", ".", "net",
+                "&", "x", "->", "y"
+            ]
+        );
+        assert_eq!(tokens[5].0, Token::Ident("system".to_string()));
+        assert_eq!(tokens[6].0, Token::Select);
+        assert_eq!(tokens[13].0, Token::Offer);
+        assert_eq!(tokens[15].0, Token::Arrow);
+        // the comment is skipped, and the next line starts at column 1
+        assert_eq!((tokens[5].1.start.line, tokens[5].1.start.column), (2, 3));
+    }
+
+    #[test]
+    fn an_unknown_token_is_an_error_at_its_position() {
+        let source = "s[user]:\n  system<+>open(Open).end\n  system<-open(Open).end";
+        let error = tokenize(source).unwrap_err();
+        assert_eq!(error.span.text(source), "<");
+        assert_eq!((error.span.start.line, error.span.start.column), (3, 9));
+        assert_eq!(error.to_string(), "3:9: unexpected `<`");
+        // a half `<+>` is unknown as well, up to where it stops matching
+        let error = tokenize("system<+open").unwrap_err();
+        assert_eq!(error.span.text("system<+open"), "<+");
+    }
+}
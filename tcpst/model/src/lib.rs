@@ -0,0 +1,39 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::fmt;
+
+/// [ProjectionError] is returned when a protocol cannot be projected onto a role,
+/// or a local type cannot be restricted to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectionError {
+    /// The role the protocol was projected onto, or the peer a local type was restricted to.
+    pub role: String,
+    pub message: String,
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot project onto `{}`: {}", self.role, self.message)
+    }
+}
+
+impl std::error::Error for ProjectionError {}
+
+pub mod check;
+pub mod codegen;
+pub mod ctx;
+pub mod export;
+pub mod global;
+pub mod lexer;
+pub mod local;
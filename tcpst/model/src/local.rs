@@ -0,0 +1,388 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::fmt;
+
+use crate::ProjectionError;
+
+/// [Local] is a local type, the view of a protocol from one role.
+/// It is printed in the syntax of `tcp.ctx`, which is also the syntax accepted by `tcp_st::session_type!`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Local {
+    /// `end`
+    End,
+    /// `t`, a jump back to the loop bound by `mu(t)`.
+    Var(String),
+    /// `mu(t)(...)`
+    Rec(String, Box<Local>),
+    /// `peer<+>{...}`
+    Select { peer: String, branches: Vec<Branch> },
+    /// `peer&{...}`
+    Offer { peer: String, branches: Vec<Branch> },
+}
+
+/// [Branch] is one `label(Sort).continuation` of a [Local] choice.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Branch {
+    pub label: String,
+    pub sort: String,
+    pub continuation: Local,
+}
+
+impl Local {
+    /// Close the loop `mu(t)(body)`, which is `end` if the body never communicates.
+    pub(crate) fn rec(variable: String, body: Local) -> Local {
+        if body.communicates() {
+            Local::Rec(variable, Box::new(body))
+        } else {
+            Local::End
+        }
+    }
+
+    /// Unfold the loops at the top of the type until it starts with an action or `end`,
+    /// replacing each jump back to a loop with the loop itself.
+    /// A jump back to a loop that does not enclose the type is left as is,
+    /// and a loop that never communicates is `end`.
+    pub fn unfold(&self) -> Local {
+        let mut local = self.clone();
+        while let Local::Rec(variable, body) = &local {
+            if !body.communicates() {
+                return Local::End;
+            }
+            local = body.substitute(variable, &local);
+        }
+        local
+    }
+
+    fn substitute(&self, variable: &str, replacement: &Local) -> Local {
+        let branches = |branches: &[Branch]| {
+            branches
+                .iter()
+                .map(|branch| Branch {
+                    continuation: branch.continuation.substitute(variable, replacement),
+                    ..branch.clone()
+                })
+                .collect()
+        };
+        match self {
+            Local::Var(bound) if bound == variable => replacement.clone(),
+            Local::End | Local::Var(_) => self.clone(),
+            Local::Rec(bound, _) if bound == variable => self.clone(),
+            Local::Rec(bound, body) => Local::Rec(
+                bound.clone(),
+                Box::new(body.substitute(variable, replacement)),
+            ),
+            Local::Select { peer, branches: b } => Local::Select {
+                peer: peer.clone(),
+                branches: branches(b),
+            },
+            Local::Offer { peer, branches: b } => Local::Offer {
+                peer: peer.clone(),
+                branches: branches(b),
+            },
+        }
+    }
+
+    fn communicates(&self) -> bool {
+        match self {
+            Local::End | Local::Var(_) => false,
+            Local::Rec(_, body) => body.communicates(),
+            Local::Select { .. } | Local::Offer { .. } => true,
+        }
+    }
+
+    /// Merge the projections of the branches of a choice the role does not take part in.
+    /// The role cannot tell which branch was taken until it receives a message,
+    /// so the projections must be equal, except for offers from the same peer,
+    /// whose branches are joined.
+    pub(crate) fn merge(self, other: Local) -> Result<Local, String> {
+        match (self, other) {
+            (left, right) if left == right => Ok(left),
+            (
+                Local::Offer { peer, branches },
+                Local::Offer {
+                    peer: other_peer,
+                    branches: other_branches,
+                },
+            ) if peer == other_peer => {
+                let mut merged = branches;
+                for branch in other_branches {
+                    match merged.iter().position(|b| b.label == branch.label) {
+                        Some(i) if merged[i].sort != branch.sort => {
+                            return Err(format!(
+                                "label `{}` is received with sorts `{}` and `{}`",
+                                branch.label, merged[i].sort, branch.sort
+                            ))
+                        }
+                        Some(i) => {
+                            let continuation =
+                                std::mem::replace(&mut merged[i].continuation, Local::End);
+                            merged[i].continuation = continuation.merge(branch.continuation)?;
+                        }
+                        None => merged.push(branch),
+                    }
+                }
+                Ok(Local::Offer {
+                    peer,
+                    branches: merged,
+                })
+            }
+            (Local::Rec(variable, body), Local::Rec(other_variable, other_body))
+                if variable == other_variable =>
+            {
+                Ok(Local::Rec(variable, Box::new(body.merge(*other_body)?)))
+            }
+            (left, right) => Err(format!("`{left}` and `{right}` cannot be merged")),
+        }
+    }
+
+    /// Restrict the local type to the actions with `peer`.
+    /// This is the binary session between the role and `peer`, which must be
+    /// the same whichever branch is taken of a choice with any other role,
+    /// except for selections sent to `peer`, whose branches are joined:
+    /// the role picks one of them, and `peer` must offer all of them anyway.
+    pub fn restrict(&self, peer: &str) -> Result<Local, ProjectionError> {
+        self.restrict_to(peer).map_err(|message| ProjectionError {
+            role: peer.to_string(),
+            message,
+        })
+    }
+
+    fn restrict_to(&self, peer: &str) -> Result<Local, String> {
+        match self {
+            Local::End | Local::Var(_) => Ok(self.clone()),
+            Local::Rec(variable, body) => Ok(Local::rec(variable.clone(), body.restrict_to(peer)?)),
+            Local::Select {
+                peer: other,
+                branches,
+            }
+            | Local::Offer {
+                peer: other,
+                branches,
+            } if other == peer => {
+                let branches = branches
+                    .iter()
+                    .map(|branch| {
+                        Ok(Branch {
+                            continuation: branch.continuation.restrict_to(peer)?,
+                            ..branch.clone()
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(match self {
+                    Local::Select { .. } => Local::Select {
+                        peer: other.clone(),
+                        branches,
+                    },
+                    _ => Local::Offer {
+                        peer: other.clone(),
+                        branches,
+                    },
+                })
+            }
+            Local::Select { branches, .. } | Local::Offer { branches, .. } => {
+                let mut restricted = branches.iter().map(|b| b.continuation.restrict_to(peer));
+                let mut first = restricted.next().unwrap_or(Ok(Local::End))?;
+                for other in restricted {
+                    first = first.join(other?).map_err(|(first, other)| {
+                        format!(
+                            "the actions with `{peer}` depend on a choice with another role: `{first}` or `{other}`"
+                        )
+                    })?;
+                }
+                Ok(first)
+            }
+        }
+    }
+
+    /// Join the restrictions of two branches of a choice with another role,
+    /// which must be equal or both select from the same peer.
+    /// A label selected in both must continue the same way.
+    fn join(self, other: Local) -> Result<Local, (Local, Local)> {
+        match (self, other) {
+            (left, right) if left == right => Ok(left),
+            (
+                Local::Select { peer, branches },
+                Local::Select {
+                    peer: other_peer,
+                    branches: other_branches,
+                },
+            ) if peer == other_peer
+                && other_branches.iter().all(|branch| {
+                    branches
+                        .iter()
+                        .all(|b| b.label != branch.label || b == branch)
+                }) =>
+            {
+                let mut joined = branches;
+                for branch in other_branches {
+                    if !joined.contains(&branch) {
+                        joined.push(branch);
+                    }
+                }
+                Ok(Local::Select {
+                    peer,
+                    branches: joined,
+                })
+            }
+            (left, right) => Err((left, right)),
+        }
+    }
+
+    /// The dual of a local type whose actions are all with the same peer,
+    /// the view of that peer of the binary session with `role`, the role of this type:
+    /// every selection becomes an offer from `role` and every offer a selection to it.
+    /// Fails if the type has actions with more than one peer.
+    pub fn dual(&self, role: &str) -> Result<Local, ProjectionError> {
+        let mut peer = None;
+        self.dual_to(role, &mut peer)
+            .map_err(|message| ProjectionError {
+                role: role.to_string(),
+                message,
+            })
+    }
+
+    fn dual_to<'a>(&'a self, role: &str, peer: &mut Option<&'a str>) -> Result<Local, String> {
+        match self {
+            Local::End | Local::Var(_) => Ok(self.clone()),
+            Local::Rec(variable, body) => Ok(Local::Rec(
+                variable.clone(),
+                Box::new(body.dual_to(role, peer)?),
+            )),
+            Local::Select {
+                peer: other,
+                branches,
+            }
+            | Local::Offer {
+                peer: other,
+                branches,
+            } => {
+                match peer {
+                    Some(first) if first != other => {
+                        return Err(format!(
+                            "the dual needs the actions of a single peer, but has actions with `{first}` and `{other}`"
+                        ))
+                    }
+                    _ => *peer = Some(other),
+                }
+                let branches = branches
+                    .iter()
+                    .map(|branch| {
+                        Ok(Branch {
+                            continuation: branch.continuation.dual_to(role, peer)?,
+                            ..branch.clone()
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                let peer = role.to_string();
+                Ok(match self {
+                    Local::Select { .. } => Local::Offer { peer, branches },
+                    _ => Local::Select { peer, branches },
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Local {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Local::End => write!(f, "end"),
+            Local::Var(variable) => write!(f, "{variable}"),
+            Local::Rec(variable, body) => write!(f, "mu({variable})({body})"),
+            Local::Select { peer, branches } => write!(f, "{peer}<+>{}", Branches(branches)),
+            Local::Offer { peer, branches } => write!(f, "{peer}&{}", Branches(branches)),
+        }
+    }
+}
+
+struct Branches<'a>(&'a [Branch]);
+
+impl fmt::Display for Branches<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let [branch] = self.0 {
+            return write!(f, "{branch}");
+        }
+        write!(f, "{{")?;
+        for (i, branch) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{branch}")?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}).{}", self.label, self.sort, self.continuation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctx::Type;
+
+    use super::Local;
+
+    fn local(source: &str) -> Local {
+        Type::parse(source).unwrap().to_local()
+    }
+
+    #[test]
+    fn restriction_joins_the_selections_that_depend_on_another_role() {
+        let system = local(
+            "user&write(Data).net<+>{
+                ok(Ack).net&{data(Ack).user<+>read(Data).end, fin(Fin).user<+>closed(Close).end},
+                lost(Ack).user<+>aborted(Close).end
+            }",
+        );
+        let view = system.restrict("user").unwrap();
+        assert_eq!(
+            view,
+            local(
+                "user&write(Data).user<+>{read(Data).end, closed(Close).end, aborted(Close).end}"
+            )
+        );
+        assert_eq!(
+            view.dual("system").unwrap(),
+            local("system<+>write(Data).system&{read(Data).end, closed(Close).end, aborted(Close).end}")
+        );
+    }
+
+    #[test]
+    fn restriction_fails_when_the_peer_cannot_tell_the_branches_apart() {
+        // the user would have to know which branch was taken to send or to receive
+        let system =
+            local("net&{ok(Ack).user&read(Data).end, lost(Ack).user<+>aborted(Close).end}");
+        assert!(system.restrict("user").is_err());
+        // the same label cannot continue in two ways
+        let system = local(
+            "net&{ok(Ack).user<+>read(Data).end, lost(Ack).user<+>read(Data).user<+>read(Data).end}",
+        );
+        assert!(system.restrict("user").is_err());
+    }
+
+    #[test]
+    fn the_dual_needs_a_single_peer() {
+        let user = local("system<+>open(Open).mu(t)(system&{data(Data).t, close(Close).end})");
+        assert_eq!(
+            user.dual("user").unwrap(),
+            local("user&open(Open).mu(t)(user<+>{data(Data).t, close(Close).end})")
+        );
+        assert!(local("system<+>open(Open).net&syn(Syn).end")
+            .dual("user")
+            .is_err());
+    }
+}
@@ -0,0 +1,292 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use pnet::packet::Packet;
+use pnet::transport::tcp_packet_iter;
+use pnet::transport::transport_channel;
+use pnet::transport::TransportChannelType::Layer4;
+use pnet::transport::TransportProtocol::Ipv4;
+use raw_socket::{Domain, Protocol, Type};
+use tcp_st::crossbeam::CrossBeamRoleChannel;
+use tcp_st::isn::Rfc6528;
+use tcp_st::multiparty::{MultipartyChannel, Route};
+use tcp_st::net_channel::{NetChannel, SERVER_PORT};
+use tcp_st::tcb::Tcb;
+use tcp_st::{Role, SessionError, SessionTypedChannel};
+
+// The payload sorts of `tcp.ctx`, where the segment sorts are the messages of `NetChannel`.
+use tcp_st::sorts::{
+    Close, Data, ErrorConnectionIllegal, ErrorInsufficientResources, ErrorRemoteUnspecified,
+    SegAckSet, SegFinAckSet, SegFinSet, SegRstSet, SegSynAckSet, SegSynSet, SocketFd, TcbInfo,
+};
+
+pub struct RoleClientSystem;
+impl Role for RoleClientSystem {}
+
+pub struct RoleClientUser;
+impl Role for RoleClientUser {}
+
+pub struct RoleServerSystem;
+impl Role for RoleServerSystem {}
+
+// The session types of the TCP client and its user, generated by the build script
+// from the `client_system` and `client_user` entries of `tcp.ctx`, and `ClientSystemUserView`,
+// the dual of the view of the user.
+include!(concat!(env!("OUT_DIR"), "/client.rs"));
+
+/// The port the client opens the connection from.
+const CLIENT_PORT: u16 = 49156;
+
+/// The data the user writes, one message per round of the data loop.
+const MESSAGES: [&str; 2] = ["hello\n", "bye\n"];
+
+/// The payload of a received segment.
+fn payload(segment: &[u8]) -> Vec<u8> {
+    TcpPacket::new(segment)
+        .map(|packet| packet.payload().to_vec())
+        .unwrap_or_default()
+}
+
+/// Build the next segment from the TCB, which the channel to the server holds.
+fn segment<'a, P, I>(
+    channel: &mut MultipartyChannel<RoleClientSystem, P>,
+    flags: u16,
+    payload: &[u8],
+) -> Vec<u8>
+where
+    P: Route<
+        RoleClientSystem,
+        RoleServerSystem,
+        I,
+        Channel = NetChannel<'a, RoleClientSystem, RoleServerSystem>,
+    >,
+{
+    channel
+        .channel::<RoleServerSystem, I>()
+        .tcb()
+        .expect("the TCB is created before the first segment")
+        .segment(flags, payload)
+}
+
+fn main() {
+    let remote_addr = Ipv4Addr::LOCALHOST;
+    let local_addr = Ipv4Addr::LOCALHOST;
+
+    // The same trick as the server: the port is held by a socket that is never used,
+    // and the RST segments the kernel sends in reply to the SYN-ACK are dropped by
+    // `iptables -A OUTPUT -p tcp --tcp-flags RST RST -j DROP`.
+    let socket =
+        raw_socket::RawSocket::new(Domain::ipv4(), Type::stream(), Some(Protocol::tcp())).unwrap();
+    socket.bind(("127.0.0.1", CLIENT_PORT)).unwrap();
+
+    let protocol = Layer4(Ipv4(IpNextHeaderProtocols::Tcp));
+    let (tx, mut rx) = match transport_channel(4096, protocol) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => panic!("An error occurred when creating the transport channel: {e}"),
+    };
+    let iter = tcp_packet_iter(&mut rx);
+    let net_channel =
+        NetChannel::<RoleClientSystem, RoleServerSystem>::new(iter, tx, local_addr, remote_addr)
+            .with_local_port(CLIENT_PORT)
+            .without_checksum_verification();
+
+    // The channel between the TCP client and its user, with the session of the user started.
+    // The view of the TCP client is not followed on its own, the multiparty session below
+    // drives the channel, and the build script checks that it is the restriction of
+    // `ClientSystemSessionType` to the user.
+    let ((mut user_system_channel, st_user), (system_user_channel, _)) =
+        CrossBeamRoleChannel::<RoleClientUser, RoleClientSystem>::pair::<
+            ClientUserSessionType,
+            ClientSystemUserView,
+        >();
+
+    let mut system_channel = MultipartyChannel::<RoleClientSystem, _>::new()
+        .with(net_channel)
+        .with(system_user_channel);
+
+    thread::scope(|scope| {
+        let user = scope.spawn(move || -> Result<(), SessionError> {
+            // The user opens the connection, writes each of the messages and closes it.
+            let cont = user_system_channel.select_one(st_user, TcbInfo { packet: vec![] })?;
+            let mut cont = match user_system_channel.offer_n(cont)? {
+                ClientUserSessionTypeChoice0::TcbCreated(_, cont) => {
+                    user_system_channel.enter(cont)?
+                }
+                ClientUserSessionTypeChoice0::ErrorNoAccess(_, cont)
+                | ClientUserSessionTypeChoice0::ErrorNoRoom(_, cont)
+                | ClientUserSessionTypeChoice0::ErrorNoRemoteSocket(_, cont) => {
+                    eprintln!("The connection could not be opened");
+                    return user_system_channel.close(cont);
+                }
+            };
+            let mut messages = MESSAGES.iter();
+            loop {
+                let Some(message) = messages.next() else {
+                    let cont = user_system_channel.select_n(
+                        cont,
+                        ClientUserSessionTypeChoice1::CloseInit,
+                        Close { packet: vec![] },
+                    )?;
+                    let (_, cont) = user_system_channel.offer_one(cont)?;
+                    return user_system_channel.close(cont);
+                };
+                let data = Data {
+                    packet: message.as_bytes().to_vec(),
+                };
+                let reply = user_system_channel.select_n(
+                    cont,
+                    ClientUserSessionTypeChoice1::WriteQueue,
+                    data,
+                )?;
+                cont = match user_system_channel.offer_n(reply)? {
+                    ClientUserSessionTypeChoice2::ReadQueue(data, cont) => {
+                        println!("Read {:?}", String::from_utf8_lossy(&data.packet));
+                        user_system_channel.recurse(cont)?
+                    }
+                    ClientUserSessionTypeChoice2::CloseInit(_, cont) => {
+                        // The server closed the connection, close our side too.
+                        let cont =
+                            user_system_channel.select_one(cont, Close { packet: vec![] })?;
+                        let (_, cont) = user_system_channel.offer_one(cont)?;
+                        return user_system_channel.close(cont);
+                    }
+                    ClientUserSessionTypeChoice2::ConnectionAborted(_, cont) => {
+                        eprintln!("The connection was aborted");
+                        return user_system_channel.close(cont);
+                    }
+                };
+            }
+        });
+        let system = scope.spawn(move || -> Result<(), SessionError> {
+            // The TCP client opens the connection actively, then moves the data
+            // of the user to the server and back until either side closes.
+            // Every segment is built from the TCB, which the channel to the server holds
+            // and checks every segment received against, so only acceptable segments are offered.
+            let st = system_channel.start::<ClientSystemSessionType>()?;
+            let (_, cont) = system_channel.offer_one(st)?;
+            let tcb = Tcb::open(
+                SocketAddrV4::new(local_addr, CLIENT_PORT),
+                SocketAddrV4::new(remote_addr, SERVER_PORT),
+                &mut Rfc6528::new(),
+            );
+            system_channel.channel::<RoleServerSystem, _>().set_tcb(tcb);
+            let cont = system_channel.select_n(
+                cont,
+                ClientSystemSessionTypeChoice0::TcbCreated,
+                SocketFd { packet: vec![] },
+            )?;
+
+            // The opening handshake.
+            let packet = segment(&mut system_channel, TcpFlags::SYN, &[]);
+            let cont = system_channel.select_one(cont, SegSynSet { packet })?;
+            let (_, cont) = system_channel.offer_one(cont)?;
+            let packet = segment(&mut system_channel, TcpFlags::ACK, &[]);
+            let cont = system_channel.select_one(cont, SegAckSet { packet })?;
+            println!("Connected");
+
+            let mut cont = system_channel.enter(cont)?;
+            loop {
+                cont = match system_channel.offer_n(cont)? {
+                    ClientSystemSessionTypeChoice1::WriteQueue(data, cont) => {
+                        let packet = segment(
+                            &mut system_channel,
+                            TcpFlags::ACK | TcpFlags::PSH,
+                            &data.packet,
+                        );
+                        let cont = system_channel.select_n(
+                            cont,
+                            ClientSystemSessionTypeChoice2::Acceptable,
+                            SegAckSet { packet },
+                        )?;
+                        match system_channel.offer_n(cont)? {
+                            ClientSystemSessionTypeChoice3::Acceptable(ack, cont) => {
+                                let packet = payload(&ack.packet);
+                                let cont = system_channel.select_one(cont, Data { packet })?;
+                                system_channel.recurse(cont)?
+                            }
+                            ClientSystemSessionTypeChoice3::Fin(_, cont) => {
+                                let cont =
+                                    system_channel.select_one(cont, Close { packet: vec![] })?;
+                                let (_, cont) = system_channel.offer_one(cont)?;
+                                let packet = segment(
+                                    &mut system_channel,
+                                    TcpFlags::FIN | TcpFlags::ACK,
+                                    &[],
+                                );
+                                let cont = system_channel.select_n(
+                                    cont,
+                                    ClientSystemSessionTypeChoice4::FinAck,
+                                    SegFinAckSet { packet },
+                                )?;
+                                let (_, cont) = system_channel.offer_one(cont)?;
+                                let cont =
+                                    system_channel.select_one(cont, Close { packet: vec![] })?;
+                                return system_channel.close(cont);
+                            }
+                            ClientSystemSessionTypeChoice3::RtoExceeded(segment, cont) => {
+                                // The channel's retransmission timer expired, the earliest
+                                // unacknowledged segment is sent again as it was.
+                                let cont = system_channel.select_one(cont, segment)?;
+                                match system_channel.offer_n(cont)? {
+                                    ClientSystemSessionTypeChoice5::Ack(_, cont) => {
+                                        // The retransmission was acknowledged without data.
+                                        let cont = system_channel
+                                            .select_one(cont, Data { packet: vec![] })?;
+                                        system_channel.recurse(cont)?
+                                    }
+                                    ClientSystemSessionTypeChoice5::RetryThresholdExceeded(
+                                        _,
+                                        cont,
+                                    ) => {
+                                        let cont = system_channel
+                                            .select_one(cont, Close { packet: vec![] })?;
+                                        return system_channel.close(cont);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    ClientSystemSessionTypeChoice1::CloseInit(_, cont) => {
+                        let packet =
+                            segment(&mut system_channel, TcpFlags::FIN | TcpFlags::ACK, &[]);
+                        let cont = system_channel.select_one(cont, SegFinSet { packet })?;
+                        let cont = match system_channel.offer_n(cont)? {
+                            ClientSystemSessionTypeChoice7::FinAck(_, cont) => {
+                                let packet = segment(&mut system_channel, TcpFlags::ACK, &[]);
+                                system_channel.select_one(cont, SegAckSet { packet })?
+                            }
+                            ClientSystemSessionTypeChoice7::Fin(_, cont) => {
+                                let (_, cont) = system_channel.offer_one(cont)?;
+                                let packet = segment(&mut system_channel, TcpFlags::ACK, &[]);
+                                system_channel.select_one(cont, SegAckSet { packet })?
+                            }
+                        };
+                        let cont = system_channel.select_one(cont, Close { packet: vec![] })?;
+                        return system_channel.close(cont);
+                    }
+                };
+            }
+        });
+        if let Err(e) = user.join().unwrap() {
+            eprintln!("The user session failed: {e}");
+        }
+        if let Err(e) = system.join().unwrap() {
+            eprintln!("The client session failed: {e}");
+        }
+    });
+}
@@ -0,0 +1,113 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{env, fs, process::ExitCode};
+
+use tcp_st::{
+    model::ctx::Context,
+    pcap::{conformance, Capture, Outcome},
+};
+
+const USAGE: &str = "usage: tcp-st-pcap [--client=ROLE] [--server=ROLE] MODEL CAPTURE...";
+
+/// Check that the TCP connections of pcap captures follow a `.ctx` model,
+/// by default the `client_system` and `server_system` roles of `tcp.ctx`.
+/// Every segment is printed with the message of the model it was classified as,
+/// and the first segment that a role does not allow is reported with the states of the role.
+/// Exits with a failure if any connection violates the model.
+fn main() -> ExitCode {
+    let mut client = "client_system".to_string();
+    let mut server = "server_system".to_string();
+    let mut files = Vec::new();
+    for argument in env::args().skip(1) {
+        if let Some(role) = argument.strip_prefix("--client=") {
+            client = role.to_string();
+        } else if let Some(role) = argument.strip_prefix("--server=") {
+            server = role.to_string();
+        } else if argument.starts_with('-') {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        } else {
+            files.push(argument);
+        }
+    }
+    if files.len() < 2 {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let model = &files[0];
+    let context = match fs::read_to_string(model) {
+        Ok(source) => match Context::parse(&source) {
+            Ok(context) => context,
+            Err(e) => {
+                eprintln!("{model}:{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        Err(e) => {
+            eprintln!("{model}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut violated = false;
+    for file in &files[1..] {
+        let segments = match fs::read(file)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| Capture::parse(&bytes).map_err(|e| e.to_string()))
+            .and_then(|capture| capture.segments().map_err(|e| e.to_string()))
+        {
+            Ok(segments) => segments,
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let connections = match conformance(&context, &client, &server, segments) {
+            Ok(connections) => connections,
+            Err(e) => {
+                eprintln!("{model}: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        for checked in connections {
+            match checked.connection.endpoints {
+                Some((from, to)) => println!("{file}, connection {from} > {to}"),
+                None => println!("{file}, connection without its opening"),
+            }
+            for (segment, message) in checked.connection.segments.iter().zip(&checked.classified) {
+                println!("  {segment}: {message}");
+            }
+            match checked.outcome {
+                Outcome::Conforms => println!("  conforms to {client} and {server}"),
+                Outcome::Incomplete {
+                    client: client_states,
+                    server: server_states,
+                } => println!(
+                    "  conforms so far, the capture ends with {client} in {client_states:?} and {server} in {server_states:?}"
+                ),
+                Outcome::Violated { frame, violation } => {
+                    violated = true;
+                    println!("  violated at frame {frame}: {violation}");
+                }
+                Outcome::Unopened => println!("  skipped, the roles are unknown without the SYN"),
+            }
+        }
+    }
+    if violated {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -11,23 +11,40 @@
  * If not, see <https://www.gnu.org/licenses/>.
  *
  */
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::Duration};
 
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 
-use crate::{Branch, Message, Role, SessionTypedChannel};
+use crate::{
+    monitor::{RawChannel, RawFrame},
+    Action, Branch, DecodeError, Dual, Message, OfferedTwo, Role, SessionError, SessionGuard,
+    SessionTypedChannel, Timed, TimedBranch, TimedOfferedTwo, Token,
+};
+
+/// [Frame] is the unit of transmission of a [CrossBeamRoleChannel].
+/// Besides the message it carries the index of the branch the sender selected,
+/// so that the receiving side of a choice observes the choice rather than guessing it.
+/// Messages sent outside of a choice carry label `0`.
+/// It also carries the [Message::sort] of the message, which a [crate::monitor::Monitor]
+/// checks before the message is decoded.
+pub struct Frame {
+    pub label: usize,
+    pub sort: &'static str,
+    pub packet: Vec<u8>,
+}
 
 /// [CrossBeamRoleChannel] is a session-typed communication channel that uses crossbeam channels under the hood.
 /// [CrossBeamRoleChannel] behaves as any other session-typed channels and implements [SessionTypedChannel].
-#[derive(Clone)]
+/// It is not [Clone], a second handle to the same channel could run a second session on it.
 pub struct CrossBeamRoleChannel<R1, R2>
 where
     R1: Role,
     R2: Role,
 {
-    pub send: Sender<Vec<u8>>,
-    pub recv: Receiver<Vec<u8>>,
-    pub phantom: PhantomData<(R1, R2)>,
+    send: Sender<Frame>,
+    recv: Receiver<Frame>,
+    guard: SessionGuard,
+    phantom: PhantomData<(R1, R2)>,
 }
 
 impl<R1, R2> CrossBeamRoleChannel<R1, R2>
@@ -35,49 +52,151 @@ where
     R1: Role,
     R2: Role,
 {
-    pub fn new(send: Sender<Vec<u8>>, recv: Receiver<Vec<u8>>) -> Self {
+    pub fn new(send: Sender<Frame>, recv: Receiver<Frame>) -> Self {
         CrossBeamRoleChannel {
             send,
             recv,
-            phantom: PhantomData::default(),
+            guard: SessionGuard::new::<Self>(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Create the two ends of a channel between `R1` and `R2` with their sessions started,
+    /// where `R1` follows the local type `A` and `R2` follows `B`, and return each end
+    /// with the first action of its session. This only compiles if `B` is the [Dual] of `A`,
+    /// and the sessions that run on the ends are the ones checked.
+    #[must_use]
+    pub fn pair<A, B>() -> ((Self, A), (CrossBeamRoleChannel<R2, R1>, B))
+    where
+        A: Action + Dual<R1, Output = B>,
+        B: Action,
+    {
+        let (send, recv) = unbounded();
+        let (peer_send, peer_recv) = unbounded();
+        let (guard, first) = SessionGuard::started::<Self, A>();
+        let (peer_guard, peer_first) = SessionGuard::started::<CrossBeamRoleChannel<R2, R1>, B>();
+        (
+            (
+                CrossBeamRoleChannel {
+                    send,
+                    recv: peer_recv,
+                    guard,
+                    phantom: PhantomData,
+                },
+                first,
+            ),
+            (
+                CrossBeamRoleChannel {
+                    send: peer_send,
+                    recv,
+                    guard: peer_guard,
+                    phantom: PhantomData,
+                },
+                peer_first,
+            ),
+        )
+    }
+}
+
+impl<R1, R2> CrossBeamRoleChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    fn send_frame(
+        &self,
+        label: usize,
+        sort: &'static str,
+        packet: Vec<u8>,
+    ) -> Result<(), SessionError> {
+        self.send
+            .send(Frame {
+                label,
+                sort,
+                packet,
+            })
+            .map_err(|_| SessionError::Disconnected)
+    }
+
+    fn recv_frame(&self) -> Result<Frame, SessionError> {
+        self.recv.recv().map_err(|_| SessionError::Disconnected)
+    }
+
+    /// Like `recv_frame`, but returns [None] if no frame arrives within `timeout`.
+    fn recv_frame_timeout(&self, timeout: Duration) -> Result<Option<Frame>, SessionError> {
+        match self.recv.recv_timeout(timeout) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(SessionError::Disconnected),
         }
     }
 }
 
+fn one_from_frame<M, A>(frame: Frame) -> Result<(M, A), SessionError>
+where
+    M: Message,
+    A: Action,
+{
+    Ok((
+        M::from_net_representation(frame.packet)?,
+        A::new(Token::new()),
+    ))
+}
+
+fn two_from_frame<M1, M2, A1, A2>(frame: Frame) -> Result<OfferedTwo<M1, M2, A1, A2>, SessionError>
+where
+    M1: Message,
+    M2: Message,
+    A1: Action,
+    A2: Action,
+{
+    match frame.label {
+        0 => Ok(Branch::Left((
+            M1::from_net_representation(frame.packet)?,
+            A1::new(Token::new()),
+        ))),
+        1 => Ok(Branch::Right((
+            M2::from_net_representation(frame.packet)?,
+            A2::new(Token::new()),
+        ))),
+        label => Err(SessionError::UnexpectedLabel(label)),
+    }
+}
+
 impl<R1, R2> SessionTypedChannel<R1, R2> for CrossBeamRoleChannel<R1, R2>
 where
     R1: Role,
     R2: Role,
 {
-    fn offer_one<M, A>(&mut self, _o: crate::OfferOne<R2, M, A>) -> (M, A)
+    fn offer_one<M, A>(&mut self, _o: crate::OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
     where
         M: crate::Message + 'static,
         A: crate::Action + 'static,
         R1: Role,
         R2: Role,
     {
-        (
-            M::from_net_representation(self.recv.recv().unwrap()),
-            A::new(),
-        )
+        one_from_frame(self.recv_frame()?)
     }
 
-    fn select_one<M, A>(&mut self, _o: crate::SelectOne<R2, M, A>, message: M) -> A
+    fn select_one<M, A>(
+        &mut self,
+        _o: crate::SelectOne<R2, M, A>,
+        message: M,
+    ) -> Result<A, SessionError>
     where
         M: crate::Message,
         A: crate::Action,
         R1: Role,
         R2: Role,
     {
-        self.send.send(message.to_net_representation()).unwrap();
-        A::new()
+        self.send_frame(0, M::sort(), message.to_net_representation())?;
+        Ok(A::new(Token::new()))
     }
 
     fn offer_two<M1, M2, A1, A2>(
         &mut self,
         _o: crate::OfferTwo<R2, M1, M2, A1, A2>,
-        picker: Box<dyn Fn() -> bool>,
-    ) -> crate::Branch<(M1, A1), (M2, A2)>
+    ) -> Result<crate::OfferedTwo<M1, M2, A1, A2>, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -86,24 +205,14 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        let choice = picker();
-        match choice {
-            true => Branch::Left((
-                M1::from_net_representation(self.recv.recv().unwrap()),
-                A1::new(),
-            )),
-            false => Branch::Right((
-                M2::from_net_representation(self.recv.recv().unwrap()),
-                A2::new(),
-            )),
-        }
+        two_from_frame(self.recv_frame()?)
     }
 
     fn select_left<M1, M2, A1, A2>(
         &mut self,
         _o: crate::SelectTwo<R2, M1, M2, A1, A2>,
         message: M1,
-    ) -> A1
+    ) -> Result<A1, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -112,15 +221,15 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        self.send.send(message.to_net_representation()).unwrap();
-        A1::new()
+        self.send_frame(0, M1::sort(), message.to_net_representation())?;
+        Ok(A1::new(Token::new()))
     }
 
     fn select_right<M1, M2, A1, A2>(
         &mut self,
         _o: crate::SelectTwo<R2, M1, M2, A1, A2>,
         message: M2,
-    ) -> A2
+    ) -> Result<A2, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -129,12 +238,137 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        self.send.send(message.to_net_representation()).unwrap();
-        A2::new()
+        self.send_frame(1, M2::sort(), message.to_net_representation())?;
+        Ok(A2::new(Token::new()))
     }
 
-    fn close(self, _end: crate::End) {
-        drop(self);
+    fn offer_n<C>(&mut self, _o: crate::OfferN<R2, C>) -> Result<C, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+    {
+        let frame = self.recv_frame()?;
+        Ok(C::from_net_representation(
+            frame.label,
+            frame.packet,
+            Token::new(),
+        )?)
+    }
+
+    fn select_n<C, M, A>(
+        &mut self,
+        _o: crate::SelectN<R2, C>,
+        label: fn(M, A) -> C,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+        M: crate::Message + 'static,
+        A: crate::Action,
+    {
+        let branch = label(message, A::new(Token::new()));
+        self.send_frame(branch.label(), M::sort(), branch.to_net_representation())?;
+        Ok(A::new(Token::new()))
+    }
+
+    fn offer_one_timeout<M, A, T>(
+        &mut self,
+        _o: Timed<crate::OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+        R1: Role,
+        R2: Role,
+    {
+        match self.recv_frame_timeout(timeout)? {
+            Some(frame) => Ok(TimedBranch::Received(one_from_frame(frame)?)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_two_timeout<M1, M2, A1, A2, T>(
+        &mut self,
+        _o: Timed<crate::OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action,
+    {
+        match self.recv_frame_timeout(timeout)? {
+            Some(frame) => Ok(TimedBranch::Received(two_from_frame(frame)?)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_n_timeout<C, T>(
+        &mut self,
+        _o: Timed<crate::OfferN<R2, C>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<C, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+        T: Action,
+    {
+        match self.recv_frame_timeout(timeout)? {
+            Some(frame) => Ok(TimedBranch::Received(C::from_net_representation(
+                frame.label,
+                frame.packet,
+                Token::new(),
+            )?)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        self.guard.start()
+    }
+
+    fn delegate(&mut self, _token: Token) {
+        self.guard.delegate();
+    }
+
+    fn close(mut self, _end: crate::End) -> Result<(), SessionError> {
+        self.guard.close();
+        Ok(())
+    }
+}
+
+impl<R1, R2> RawChannel for CrossBeamRoleChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    fn receive_raw(
+        &mut self,
+        timeout: Option<Duration>,
+        _labels: &[&str],
+    ) -> Result<Option<RawFrame>, SessionError> {
+        let frame = match timeout {
+            Some(timeout) => self.recv_frame_timeout(timeout)?,
+            None => Some(self.recv_frame()?),
+        };
+        Ok(frame.map(|frame| RawFrame {
+            label: Some(frame.label),
+            sort: Some(frame.sort),
+            packet: frame.packet,
+        }))
     }
 }
 
@@ -145,8 +379,8 @@ impl Message for Open {
         vec![]
     }
 
-    fn from_net_representation(_packet: Vec<u8>) -> Self {
-        Open {}
+    fn from_net_representation(_packet: Vec<u8>) -> Result<Self, DecodeError> {
+        Ok(Open {})
     }
 }
 
@@ -157,8 +391,8 @@ impl Message for TcbCreated {
         vec![]
     }
 
-    fn from_net_representation(_packet: Vec<u8>) -> Self {
-        TcbCreated {}
+    fn from_net_representation(_packet: Vec<u8>) -> Result<Self, DecodeError> {
+        Ok(TcbCreated {})
     }
 }
 
@@ -169,8 +403,8 @@ impl Message for Close {
         vec![]
     }
 
-    fn from_net_representation(_packet: Vec<u8>) -> Self {
-        Close {}
+    fn from_net_representation(_packet: Vec<u8>) -> Result<Self, DecodeError> {
+        Ok(Close {})
     }
 }
 
@@ -181,7 +415,7 @@ impl Message for Connected {
         vec![]
     }
 
-    fn from_net_representation(_packet: Vec<u8>) -> Self {
-        Connected {}
+    fn from_net_representation(_packet: Vec<u8>) -> Result<Self, DecodeError> {
+        Ok(Connected {})
     }
 }
@@ -0,0 +1,233 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::any::type_name;
+
+pub use tcp_st_model::local::{Branch, Local};
+
+use crate::{
+    Choice, End, Message, OfferN, OfferOne, OfferTwo, Rec, RecursionVariable, Role, SelectN,
+    SelectOne, SelectTwo, Timed, Var,
+};
+
+/// [Describe] turns a session type into the [Local] type of the model it stands for,
+/// so that it can be exported with `tcp_st_model::export` like the types of a `.ctx` file.
+/// Roles are named after the Rust types, `RoleServerSystem` is `server_system`, and messages
+/// after their [Message::sort], e.g. `SegSynSet` is the sort `SegSynSet` with label `seg_syn_set` outside of n-ary choices.
+/// Loops are named after their [RecursionVariable] marker.
+/// A [Timed] offer is described as its offer with an extra `timeout(Timeout)` branch.
+pub trait Describe {
+    fn describe() -> Local;
+}
+
+/// [DescribeChoice] describes the branches of a [Choice], it is implemented by the [choice] macro.
+pub trait DescribeChoice: Choice {
+    fn branches() -> Vec<Branch>;
+}
+
+/// The [Local] type of the session type `A`.
+pub fn describe<A>() -> Local
+where
+    A: Describe,
+{
+    A::describe()
+}
+
+/// The name of `T` without its module path or generic arguments.
+pub(crate) fn short_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// `CamelCase` to `snake_case`.
+pub fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// The name of the role `R` in the model, e.g. `RoleServerSystem` is `server_system`.
+pub fn role<R>() -> String {
+    let name = short_name::<R>();
+    snake_case(name.strip_prefix("Role").unwrap_or(name))
+}
+
+fn branch<M, A>(label: String) -> Branch
+where
+    M: Message,
+    A: Describe,
+{
+    Branch {
+        label,
+        sort: M::sort().to_string(),
+        continuation: A::describe(),
+    }
+}
+
+fn message_branch<M, A>() -> Branch
+where
+    M: Message,
+    A: Describe,
+{
+    branch::<M, A>(snake_case(M::sort()))
+}
+
+/// A branch of a [DescribeChoice], used by the [choice] macro.
+pub fn choice_branch<M, A>(label: &str) -> Branch
+where
+    M: Message,
+    A: Describe,
+{
+    branch::<M, A>(snake_case(label))
+}
+
+impl Describe for End {
+    fn describe() -> Local {
+        Local::End
+    }
+}
+
+impl<R, M, A> Describe for OfferOne<R, M, A>
+where
+    R: Role,
+    M: Message,
+    A: crate::Action + Describe,
+{
+    fn describe() -> Local {
+        Local::Offer {
+            peer: role::<R>(),
+            branches: vec![message_branch::<M, A>()],
+        }
+    }
+}
+
+impl<R, M, A> Describe for SelectOne<R, M, A>
+where
+    R: Role,
+    M: Message,
+    A: crate::Action + Describe,
+{
+    fn describe() -> Local {
+        Local::Select {
+            peer: role::<R>(),
+            branches: vec![message_branch::<M, A>()],
+        }
+    }
+}
+
+impl<R, M1, M2, A1, A2> Describe for OfferTwo<R, M1, M2, A1, A2>
+where
+    R: Role,
+    M1: Message,
+    M2: Message,
+    A1: crate::Action + Describe,
+    A2: crate::Action + Describe,
+{
+    fn describe() -> Local {
+        Local::Offer {
+            peer: role::<R>(),
+            branches: vec![message_branch::<M1, A1>(), message_branch::<M2, A2>()],
+        }
+    }
+}
+
+impl<R, M1, M2, A1, A2> Describe for SelectTwo<R, M1, M2, A1, A2>
+where
+    R: Role,
+    M1: Message,
+    M2: Message,
+    A1: crate::Action + Describe,
+    A2: crate::Action + Describe,
+{
+    fn describe() -> Local {
+        Local::Select {
+            peer: role::<R>(),
+            branches: vec![message_branch::<M1, A1>(), message_branch::<M2, A2>()],
+        }
+    }
+}
+
+impl<R, C> Describe for OfferN<R, C>
+where
+    R: Role,
+    C: DescribeChoice,
+{
+    fn describe() -> Local {
+        Local::Offer {
+            peer: role::<R>(),
+            branches: C::branches(),
+        }
+    }
+}
+
+impl<R, C> Describe for SelectN<R, C>
+where
+    R: Role,
+    C: DescribeChoice,
+{
+    fn describe() -> Local {
+        Local::Select {
+            peer: role::<R>(),
+            branches: C::branches(),
+        }
+    }
+}
+
+impl<O, T> Describe for Timed<O, T>
+where
+    O: Describe,
+    T: Describe,
+{
+    fn describe() -> Local {
+        match O::describe() {
+            Local::Offer { peer, mut branches } => {
+                branches.push(Branch {
+                    label: "timeout".to_string(),
+                    sort: "Timeout".to_string(),
+                    continuation: T::describe(),
+                });
+                Local::Offer { peer, branches }
+            }
+            offer => offer,
+        }
+    }
+}
+
+impl<T> Describe for Rec<T>
+where
+    T: RecursionVariable,
+    T::Body: Describe,
+{
+    fn describe() -> Local {
+        Local::Rec(snake_case(short_name::<T>()), Box::new(T::Body::describe()))
+    }
+}
+
+impl<T> Describe for Var<T>
+where
+    T: Send,
+{
+    fn describe() -> Local {
+        Local::Var(snake_case(short_name::<T>()))
+    }
+}
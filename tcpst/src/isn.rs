@@ -0,0 +1,200 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddrV4,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::sequence::SeqNum;
+
+/// [IsnSource] picks the initial send sequence number of a connection.
+/// Any `FnMut(local, remote) -> SeqNum` is a source, e.g. a fixed number in a test.
+pub trait IsnSource {
+    fn isn(&mut self, local: SocketAddrV4, remote: SocketAddrV4) -> SeqNum;
+}
+
+impl<F> IsnSource for F
+where
+    F: FnMut(SocketAddrV4, SocketAddrV4) -> SeqNum,
+{
+    fn isn(&mut self, local: SocketAddrV4, remote: SocketAddrV4) -> SeqNum {
+        self(local, remote)
+    }
+}
+
+/// [Rfc6528] generates initial sequence numbers as in RFC 6528: `ISN = M + F(4-tuple, key)`,
+/// where `M` is a clock ticking every 4 microseconds and `F` is SipHash-2-4 of the 4-tuple
+/// of the connection under a secret key.
+/// The clock keeps the numbers of successive connections between the same sockets increasing,
+/// so old duplicate segments fall outside the new connection, and the key keeps an off-path
+/// attacker from predicting them.
+pub struct Rfc6528<C = fn() -> Duration> {
+    key: (u64, u64),
+    clock: C,
+}
+
+impl Rfc6528 {
+    /// A generator with a random key, on the [system_clock].
+    #[must_use]
+    pub fn new() -> Self {
+        let random = || RandomState::new().build_hasher().finish();
+        Rfc6528 {
+            key: (random(), random()),
+            clock: system_clock,
+        }
+    }
+}
+
+impl Default for Rfc6528 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> Rfc6528<C>
+where
+    C: Fn() -> Duration,
+{
+    /// A generator with the given key and clock, e.g. the virtual clock of a
+    /// [crate::simulated::Network], which makes the numbers deterministic.
+    pub fn with_key(key: (u64, u64), clock: C) -> Self {
+        Rfc6528 { key, clock }
+    }
+}
+
+impl<C> IsnSource for Rfc6528<C>
+where
+    C: Fn() -> Duration,
+{
+    fn isn(&mut self, local: SocketAddrV4, remote: SocketAddrV4) -> SeqNum {
+        let ticks = ((self.clock)().as_micros() / 4) as u32;
+        let mut tuple = [0; 12];
+        tuple[..4].copy_from_slice(&local.ip().octets());
+        tuple[4..6].copy_from_slice(&local.port().to_be_bytes());
+        tuple[6..10].copy_from_slice(&remote.ip().octets());
+        tuple[10..].copy_from_slice(&remote.port().to_be_bytes());
+        SeqNum::new(ticks) + siphash(self.key, &tuple) as u32
+    }
+}
+
+/// The time since the Unix epoch, which keeps counting across restarts.
+#[must_use]
+pub fn system_clock() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// SipHash-2-4 of `data` under `key`.
+fn siphash(key: (u64, u64), data: &[u8]) -> u64 {
+    let mut v = [
+        key.0 ^ 0x736f_6d65_7073_6575,
+        key.1 ^ 0x646f_7261_6e64_6f6d,
+        key.0 ^ 0x6c79_6765_6e65_7261,
+        key.1 ^ 0x7465_6462_7974_6573,
+    ];
+    let rounds = |v: &mut [u64; 4], n: usize| {
+        for _ in 0..n {
+            v[0] = v[0].wrapping_add(v[1]);
+            v[1] = v[1].rotate_left(13) ^ v[0];
+            v[0] = v[0].rotate_left(32);
+            v[2] = v[2].wrapping_add(v[3]);
+            v[3] = v[3].rotate_left(16) ^ v[2];
+            v[0] = v[0].wrapping_add(v[3]);
+            v[3] = v[3].rotate_left(21) ^ v[0];
+            v[2] = v[2].wrapping_add(v[1]);
+            v[1] = v[1].rotate_left(17) ^ v[2];
+            v[2] = v[2].rotate_left(32);
+        }
+    };
+    let chunks = data.chunks_exact(8);
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    for word in chunks
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("chunks have 8 bytes")))
+        .chain([u64::from_le_bytes(last)])
+    {
+        v[3] ^= word;
+        rounds(&mut v, 2);
+        v[0] ^= word;
+    }
+    v[2] ^= 0xff;
+    rounds(&mut v, 4);
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        net::{Ipv4Addr, SocketAddrV4},
+        time::Duration,
+    };
+
+    use super::{siphash, IsnSource, Rfc6528};
+
+    /// The key `00 01 .. 0f` of the test vectors of the SipHash paper.
+    const KEY: (u64, u64) = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+
+    #[test]
+    fn siphash_matches_the_reference_vectors() {
+        // SipHash-2-4 of the messages `00 01 .. n-1`, from the vectors of the reference implementation
+        let vectors = [
+            (0, 0x726f_db47_dd0e_0e31),
+            (1, 0x74f8_39c5_93dc_67fd),
+            (2, 0x0d6c_8009_d9a9_4f5a),
+            (3, 0x8567_6696_d7fb_7e2d),
+            (7, 0xab02_00f5_8b01_d137),
+            (8, 0x93f5_f579_9a93_2462),
+            // the example of appendix A of the paper
+            (15, 0xa129_ca61_49be_45e5),
+            (63, 0x958a_324c_eb06_4572),
+        ];
+        for (length, expected) in vectors {
+            let message: Vec<u8> = (0..length).collect();
+            assert_eq!(
+                siphash(KEY, &message),
+                expected,
+                "message of {length} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn isn_advances_with_the_clock() {
+        let local = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+        let remote = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156);
+        let now = Cell::new(Duration::from_secs(1));
+        let mut isn = Rfc6528::with_key(KEY, || now.get());
+
+        let first = isn.isn(local, remote);
+        assert_eq!(isn.isn(local, remote), first);
+        now.set(now.get() + Duration::from_micros(3));
+        assert_eq!(isn.isn(local, remote), first);
+        now.set(now.get() + Duration::from_micros(1));
+        assert_eq!(isn.isn(local, remote) - first, 1);
+        now.set(now.get() + Duration::from_millis(1));
+        assert_eq!(isn.isn(local, remote) - first, 251);
+
+        // the clock wraps around sequence space after 2^32 ticks of 4 microseconds
+        now.set(now.get() + Duration::from_micros(4 << 32));
+        assert_eq!(isn.isn(local, remote) - first, 251);
+
+        // another 4-tuple is offset by another hash
+        assert_ne!(isn.isn(remote, local), isn.isn(local, remote));
+    }
+}
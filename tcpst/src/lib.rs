@@ -11,21 +11,162 @@
  * If not, see <https://www.gnu.org/licenses/>.
  *
  */
-use std::marker::PhantomData;
+use std::{any::Any, fmt, marker::PhantomData, thread, time::Duration};
 
 // Supporting traits
 
+/// [Action] is implemented by the session action types, which are the states of a local type.
+/// A value of an action type is the right to perform that action exactly once:
+/// the operations of a [SessionTypedChannel] consume it and return the next one.
+/// Creating an action needs a [Token], which only this crate can make,
+/// so a session can only be started with [SessionTypedChannel::start] and advanced by its operations.
+/// See `doc/soundness.md` for what the encoding does and does not guarantee.
 pub trait Action: Send {
-    fn new() -> Self
+    fn new(token: Token) -> Self
     where
         Self: Sized;
 }
 
+/// [Token] is the capability to create an [Action].
+/// It cannot be built outside of this crate, it is handed out by the channels
+/// to [Choice::from_net_representation] for the continuation of the received branch.
+pub struct Token {
+    _private: (),
+}
+
+impl Token {
+    pub(crate) fn new() -> Self {
+        Token { _private: () }
+    }
+}
+
 pub trait Role {}
 
 pub trait Message: Send {
     fn to_net_representation(self) -> Vec<u8>;
-    fn from_net_representation(packet: Vec<u8>) -> Self;
+
+    /// Decode the message from its on-the-wire representation.
+    /// Fails if `packet` is not a valid representation of this message,
+    /// so holding a message means holding what its type claims.
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError>
+    where
+        Self: Sized;
+
+    /// Whether `packet` is a representation of this message.
+    /// Channels without an explicit label on the wire use this to find out
+    /// which branch of a choice the peer selected, see [net_channel::Classify].
+    fn matches(_packet: &[u8]) -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
+    /// The payload sort of the model that this message stands for, by default the name of the type.
+    /// Monitors and exporters compare messages with the model by their sort.
+    fn sort() -> &'static str
+    where
+        Self: Sized,
+    {
+        describe::short_name::<Self>()
+    }
+}
+
+/// [DecodeError] is returned when a received packet is not a valid
+/// representation of the message type it is decoded into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The packet is shorter than the header of the message.
+    Truncated { length: usize },
+    /// The header length field of the packet is out of range, the value is in 32-bit words.
+    HeaderLength(u8),
+    /// The control flags of the packet do not belong to the message type.
+    Flags(u16),
+    /// The checksum of the packet does not match its contents.
+    Checksum { expected: u16, found: u16 },
+    /// The label of a choice does not name any of its branches.
+    Label(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { length } => write!(f, "packet of {length} bytes is truncated"),
+            DecodeError::HeaderLength(words) => write!(f, "invalid header length of {words} words"),
+            DecodeError::Flags(flags) => write!(f, "unexpected flags {flags:#05x}"),
+            DecodeError::Checksum { expected, found } => {
+                write!(f, "checksum {found:#06x} does not match {expected:#06x}")
+            }
+            DecodeError::Label(label) => write!(f, "no branch with label {label}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// [SessionError] is returned by the operations of a [SessionTypedChannel] that could not be completed.
+#[derive(Debug)]
+pub enum SessionError {
+    /// The peer went away or the underlying medium was closed.
+    Disconnected,
+    /// A message was received but could not be decoded.
+    Decode(DecodeError),
+    /// No message arrived in time.
+    Timeout,
+    /// The peer selected a branch that is not offered.
+    UnexpectedLabel(usize),
+    /// The underlying medium failed.
+    Io(std::io::Error),
+    /// A session was already started on the channel.
+    AlreadyStarted,
+    /// A message was sent or received that the model does not allow, see [monitor::Monitor].
+    Violation(monitor::Violation),
+    /// A segment was retransmitted as often as allowed without being acknowledged,
+    /// see [retransmission::RetransmissionQueue].
+    RetryThresholdExceeded,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Disconnected => write!(f, "peer disconnected"),
+            SessionError::Decode(e) => write!(f, "failed to decode message: {e}"),
+            SessionError::Timeout => write!(f, "timed out waiting for a message"),
+            SessionError::UnexpectedLabel(label) => write!(f, "unexpected label {label}"),
+            SessionError::Io(e) => write!(f, "I/O error: {e}"),
+            SessionError::AlreadyStarted => write!(f, "session already started"),
+            SessionError::Violation(v) => write!(f, "protocol violation: {v}"),
+            SessionError::RetryThresholdExceeded => write!(f, "retransmission threshold exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Decode(e) => Some(e),
+            SessionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for SessionError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Label(label) => SessionError::UnexpectedLabel(label),
+            e => SessionError::Decode(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for SessionError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => SessionError::Timeout,
+            _ => SessionError::Io(e),
+        }
+    }
 }
 
 // Session action types
@@ -45,10 +186,7 @@ where
     A: Action,
     R: Role + std::marker::Send,
 {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
+    fn new(_token: Token) -> Self {
         OfferOne {
             phantom: PhantomData,
         }
@@ -70,10 +208,7 @@ where
     A: Action,
     R: Role + std::marker::Send,
 {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
+    fn new(_token: Token) -> Self {
         SelectOne {
             phantom: PhantomData,
         }
@@ -99,12 +234,9 @@ where
     A1: Action,
     A2: Action,
 {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
+    fn new(_token: Token) -> Self {
         OfferTwo {
-            phantom: PhantomData::default(),
+            phantom: PhantomData,
         }
     }
 }
@@ -114,6 +246,9 @@ pub enum Branch<L, R> {
     Right(R),
 }
 
+/// The branch taken by the peer in an [OfferTwo], with its message and continuation.
+pub type OfferedTwo<M1, M2, A1, A2> = Branch<(M1, A1), (M2, A2)>;
+
 pub struct SelectTwo<R, M1, M2, A1, A2>
 where
     R: Role,
@@ -133,50 +268,455 @@ where
     A1: Action,
     A2: Action,
 {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
+    fn new(_token: Token) -> Self {
         SelectTwo {
-            phantom: PhantomData::default(),
+            phantom: PhantomData,
         }
     }
 }
 
+/// [Choice] is implemented by the enum of labelled branches of an [OfferN] or [SelectN].
+/// Each variant of the enum holds the message and continuation of one branch.
+/// Implementations are generated by the [choice] macro.
+pub trait Choice: Send + Sized {
+    /// The names of the labels of the choice, in branch order.
+    const LABELS: &'static [&'static str];
+
+    /// The index of the branch `self` was built from.
+    fn label(&self) -> usize;
+
+    /// Build the branch with index `label` out of a received packet,
+    /// using `token` to create the continuation of the branch.
+    /// Fails with [DecodeError::Label] if there is no such branch.
+    fn from_net_representation(
+        label: usize,
+        packet: Vec<u8>,
+        token: Token,
+    ) -> Result<Self, DecodeError>;
+
+    /// Turn the message of the branch into its on-the-wire representation.
+    fn to_net_representation(self) -> Vec<u8>;
+
+    /// Take the message out of the branch, dropping its continuation.
+    fn into_message(self) -> Box<dyn Any + Send>;
+
+    /// The index of the first branch whose message matches `packet`, see [Message::matches].
+    fn label_of(packet: &[u8]) -> Option<usize>;
+
+    /// The index of the first branch whose message matches `packet` and is of sort `sort`,
+    /// for channels that classify a packet by more than its representation,
+    /// e.g. [net_channel::NetChannel] by the [net_channel::segment_sort] of a segment.
+    fn label_of_sort(packet: &[u8], sort: &str) -> Option<usize>;
+}
+
+/// Declare an enum of labelled branches and implement [Choice] for it.
+/// Each variant takes the message type and the continuation of its branch:
+///
+/// ```ignore
+/// choice! {
+///     pub enum DataTransfer {
+///         Acceptable(Ack, Var<DataLoop>),
+///         RtoExceeded(Ack, SelectOne<RoleServerClient, Ack, Var<DataLoop>>),
+///         Fin(FinAck, End),
+///     }
+/// }
+/// ```
+///
+/// Followed by `dual <vis> enum <Name> for <Role> => <Peer>;` it also declares the
+/// enum of the peer's side of the choice and implements [Dual] between the two,
+/// where `<Role>` is the role whose local type contains the first enum.
+#[macro_export]
+macro_rules! choice {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($label:ident($message:ty, $cont:ty)),+ $(,)?
+        }
+        dual $dual_vis:vis enum $dual:ident for $role:ty => $peer:ty;
+    ) => {
+        $crate::choice! {
+            $(#[$meta])*
+            $vis enum $name {
+                $($label($message, $cont)),+
+            }
+        }
+
+        $crate::choice! {
+            $dual_vis enum $dual {
+                $($label($message, <$cont as $crate::Dual<$role>>::Output)),+
+            }
+        }
+
+        impl $crate::Dual<$role> for $name {
+            type Output = $dual;
+        }
+
+        impl $crate::Dual<$peer> for $dual {
+            type Output = $name;
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($label:ident($message:ty, $cont:ty)),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($label($message, $cont)),+
+        }
+
+        impl $crate::Choice for $name {
+            const LABELS: &'static [&'static str] = &[$(stringify!($label)),+];
+
+            fn label(&self) -> usize {
+                // the discriminants of an enum with the same variants are their positions
+                enum Position {
+                    $($label),+
+                }
+                match self {
+                    $(Self::$label(..) => Position::$label as usize),+
+                }
+            }
+
+            fn from_net_representation(
+                label: usize,
+                packet: Vec<u8>,
+                token: $crate::Token,
+            ) -> Result<Self, $crate::DecodeError> {
+                enum Position {
+                    $($label),+
+                }
+                $(if label == Position::$label as usize {
+                    return Ok(Self::$label(
+                        <$message as $crate::Message>::from_net_representation(packet)?,
+                        <$cont as $crate::Action>::new(token),
+                    ));
+                })+
+                Err($crate::DecodeError::Label(label))
+            }
+
+            fn to_net_representation(self) -> Vec<u8> {
+                match self {
+                    $(Self::$label(message, _) => $crate::Message::to_net_representation(message)),+
+                }
+            }
+
+            fn into_message(self) -> Box<dyn ::std::any::Any + Send> {
+                match self {
+                    $(Self::$label(message, _) => Box::new(message)),+
+                }
+            }
+
+            fn label_of(packet: &[u8]) -> Option<usize> {
+                [$(<$message as $crate::Message>::matches(packet)),+]
+                    .iter()
+                    .position(|matches| *matches)
+            }
+
+            fn label_of_sort(packet: &[u8], sort: &str) -> Option<usize> {
+                [$(
+                    <$message as $crate::Message>::matches(packet)
+                        && <$message as $crate::Message>::sort() == sort
+                ),+]
+                    .iter()
+                    .position(|matches| *matches)
+            }
+        }
+
+        impl $crate::describe::DescribeChoice for $name {
+            fn branches() -> Vec<$crate::describe::Branch> {
+                vec![$($crate::describe::choice_branch::<$message, $cont>(stringify!($label))),+]
+            }
+        }
+    };
+}
+
+/// [OfferN] offers a choice between the labelled branches of `C`.
+/// This generalises [OfferTwo] to any number of branches.
+pub struct OfferN<R, C>
+where
+    R: Role,
+    C: Choice,
+{
+    phantom: PhantomData<(R, C)>,
+}
+
+impl<R, C> Action for OfferN<R, C>
+where
+    R: Role + std::marker::Send,
+    C: Choice,
+{
+    fn new(_token: Token) -> Self {
+        OfferN {
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// [SelectN] selects one of the labelled branches of `C`.
+/// This generalises [SelectTwo] to any number of branches.
+pub struct SelectN<R, C>
+where
+    R: Role,
+    C: Choice,
+{
+    phantom: PhantomData<(R, C)>,
+}
+
+impl<R, C> Action for SelectN<R, C>
+where
+    R: Role + std::marker::Send,
+    C: Choice,
+{
+    fn new(_token: Token) -> Self {
+        SelectN {
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// [Timed] turns the offer `O` into an offer that gives up after a timeout.
+/// If no message arrives in time the session continues with `T`,
+/// so timers such as the retransmission timeout are part of the session type.
+pub struct Timed<O, T> {
+    phantom: PhantomData<(O, T)>,
+}
+
+impl<O, T> Action for Timed<O, T>
+where
+    O: Action,
+    T: Action,
+{
+    fn new(_token: Token) -> Self {
+        Timed {
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// The outcome of a [Timed] offer: either what the offer received,
+/// or the timeout continuation if nothing arrived in time.
+pub enum TimedBranch<B, T> {
+    Received(B),
+    TimedOut(T),
+}
+
+/// The outcome of a [Timed] [OfferTwo].
+pub type TimedOfferedTwo<M1, M2, A1, A2, T> = TimedBranch<OfferedTwo<M1, M2, A1, A2>, T>;
+
 pub struct End {}
 
 impl Action for End {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
+    fn new(_token: Token) -> Self {
         End {}
     }
 }
 
+/// [RecursionVariable] names a recursive session type `mu(t)(...)`.
+/// Each loop in a protocol is given its own marker type implementing this trait,
+/// with [RecursionVariable::Body] set to the body of the loop.
+/// The body refers back to the start of the loop through [Var].
+pub trait RecursionVariable: Send {
+    type Body: Action;
+}
+
+/// [Rec] marks the entry point of the loop named by `T`.
+/// It is unfolded into `T::Body` by [SessionTypedChannel::enter].
+pub struct Rec<T>
+where
+    T: RecursionVariable,
+{
+    phantom: PhantomData<T>,
+}
+
+impl<T> Action for Rec<T>
+where
+    T: RecursionVariable,
+{
+    fn new(_token: Token) -> Self {
+        Rec {
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// [Var] jumps back to the start of the loop named by `T`.
+/// It is unfolded into `T::Body` by [SessionTypedChannel::recurse].
+/// Unlike [Rec], [Var] does not require `T` to be a [RecursionVariable],
+/// so that it can appear inside the body of the loop it refers to.
+pub struct Var<T>
+where
+    T: Send,
+{
+    phantom: PhantomData<T>,
+}
+
+impl<T> Action for Var<T>
+where
+    T: Send,
+{
+    fn new(_token: Token) -> Self {
+        Var {
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// [Dual] maps a binary local type of role `R` to the local type its peer must follow,
+/// swapping each offer for a select of the same messages and vice versa.
+/// The role named by each action of the output is `R`, the peer's peer.
+/// [Dual] is what channel pair constructors such as
+/// [crossbeam::CrossBeamRoleChannel::pair] use to reject mismatched local types at compile time.
+///
+/// Recursion variables and the enums of n-ary choices are user-defined,
+/// so their duals are declared alongside them: a loop marker implements [Dual]
+/// by naming the marker of the dual loop, and the dual of a choice enum is
+/// generated by the `dual` form of [choice].
+/// [Timed] offers have no dual, the timeout branch is not observed by the peer.
+pub trait Dual<R>
+where
+    R: Role,
+{
+    type Output;
+}
+
+impl<R> Dual<R> for End
+where
+    R: Role,
+{
+    type Output = End;
+}
+
+impl<R, R2, M, A> Dual<R> for OfferOne<R2, M, A>
+where
+    R: Role + Send,
+    R2: Role,
+    M: Message,
+    A: Action + Dual<R>,
+    A::Output: Action,
+{
+    type Output = SelectOne<R, M, A::Output>;
+}
+
+impl<R, R2, M, A> Dual<R> for SelectOne<R2, M, A>
+where
+    R: Role + Send,
+    R2: Role,
+    M: Message,
+    A: Action + Dual<R>,
+    A::Output: Action,
+{
+    type Output = OfferOne<R, M, A::Output>;
+}
+
+impl<R, R2, M1, M2, A1, A2> Dual<R> for OfferTwo<R2, M1, M2, A1, A2>
+where
+    R: Role + Send,
+    R2: Role,
+    M1: Message,
+    M2: Message,
+    A1: Action + Dual<R>,
+    A2: Action + Dual<R>,
+    A1::Output: Action,
+    A2::Output: Action,
+{
+    type Output = SelectTwo<R, M1, M2, A1::Output, A2::Output>;
+}
+
+impl<R, R2, M1, M2, A1, A2> Dual<R> for SelectTwo<R2, M1, M2, A1, A2>
+where
+    R: Role + Send,
+    R2: Role,
+    M1: Message,
+    M2: Message,
+    A1: Action + Dual<R>,
+    A2: Action + Dual<R>,
+    A1::Output: Action,
+    A2::Output: Action,
+{
+    type Output = OfferTwo<R, M1, M2, A1::Output, A2::Output>;
+}
+
+impl<R, R2, C> Dual<R> for OfferN<R2, C>
+where
+    R: Role + Send,
+    R2: Role,
+    C: Choice + Dual<R>,
+    C::Output: Choice,
+{
+    type Output = SelectN<R, C::Output>;
+}
+
+impl<R, R2, C> Dual<R> for SelectN<R2, C>
+where
+    R: Role + Send,
+    R2: Role,
+    C: Choice + Dual<R>,
+    C::Output: Choice,
+{
+    type Output = OfferN<R, C::Output>;
+}
+
+/// The dual of a loop is the loop named by the dual of its marker,
+/// whose body must be the dual of the body of the original loop.
+impl<R, T> Dual<R> for Rec<T>
+where
+    R: Role,
+    T: RecursionVariable + Dual<R>,
+    T::Body: Dual<R>,
+    T::Output: RecursionVariable<Body = <T::Body as Dual<R>>::Output>,
+{
+    type Output = Rec<T::Output>;
+}
+
+impl<R, T> Dual<R> for Var<T>
+where
+    R: Role,
+    T: Send + Dual<R>,
+    T::Output: Send,
+{
+    type Output = Var<T::Output>;
+}
+
+/// [SessionTypedChannel] is implemented by the communication channels between roles `R1` and `R2`.
+/// Every operation consumes the continuation it is given and returns the next one.
+/// If the operation fails, the [SessionError] is returned instead and the
+/// continuation is gone, so a session cannot be resumed past a failed step.
 pub trait SessionTypedChannel<R1, R2> {
-    #[must_use]
-    fn offer_one<M, A>(&mut self, _o: OfferOne<R2, M, A>) -> (M, A)
+    /// Start the session of local type `A` on the channel.
+    /// This is the only way to get hold of the first action of a session,
+    /// and it fails with [SessionError::AlreadyStarted] if the channel was started before.
+    ///
+    /// `A` is not checked against the roles of the channel or any protocol, the caller
+    /// vouches that it is the right local type, see `doc/soundness.md`.
+    fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action;
+
+    /// Hand the session of the channel over to a [multiparty::MultipartyChannel], which
+    /// drives it from then on. The channel cannot be started on its own any more, and
+    /// dropping it is not reported, the multiparty session is. Only callable in this crate.
+    fn delegate(&mut self, token: Token);
+
+    fn offer_one<M, A>(&mut self, _o: OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
     where
         M: Message + 'static,
         A: Action + 'static,
         R1: Role,
         R2: Role;
 
-    #[must_use]
-    fn select_one<M, A>(&mut self, _o: SelectOne<R2, M, A>, message: M) -> A
+    fn select_one<M, A>(&mut self, _o: SelectOne<R2, M, A>, message: M) -> Result<A, SessionError>
     where
         M: Message,
         A: Action,
         R1: Role,
         R2: Role;
 
-    #[must_use]
     fn offer_two<M1, M2, A1, A2>(
         &mut self,
         _o: OfferTwo<R2, M1, M2, A1, A2>,
-        picker: Box<dyn Fn() -> bool>,
-    ) -> Branch<(M1, A1), (M2, A2)>
+    ) -> Result<OfferedTwo<M1, M2, A1, A2>, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -185,8 +725,11 @@ pub trait SessionTypedChannel<R1, R2> {
         A1: Action,
         A2: Action;
 
-    #[must_use]
-    fn select_left<M1, M2, A1, A2>(&mut self, _o: SelectTwo<R2, M1, M2, A1, A2>, message: M1) -> A1
+    fn select_left<M1, M2, A1, A2>(
+        &mut self,
+        _o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M1,
+    ) -> Result<A1, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -195,12 +738,11 @@ pub trait SessionTypedChannel<R1, R2> {
         A1: Action,
         A2: Action;
 
-    #[must_use]
     fn select_right<M1, M2, A1, A2>(
         &mut self,
         _o: SelectTwo<R2, M1, M2, A1, A2>,
         message: M2,
-    ) -> A2
+    ) -> Result<A2, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -209,8 +751,260 @@ pub trait SessionTypedChannel<R1, R2> {
         A1: Action,
         A2: Action;
 
-    fn close(self, end: End);
+    /// Offer a choice between the branches of `C`, returning the branch the peer selected.
+    fn offer_n<C>(&mut self, _o: OfferN<R2, C>) -> Result<C, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: Choice;
+
+    /// Select the branch of `C` built by the enum variant `label`, e.g. `DataTransfer::Fin`.
+    fn select_n<C, M, A>(
+        &mut self,
+        _o: SelectN<R2, C>,
+        label: fn(M, A) -> C,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: Choice,
+        M: Message + 'static,
+        A: Action;
+
+    /// Like [SessionTypedChannel::offer_one], but continue with `T` if no message arrives within `timeout`.
+    fn offer_one_timeout<M, A, T>(
+        &mut self,
+        _o: Timed<OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+        R1: Role,
+        R2: Role;
+
+    /// Like [SessionTypedChannel::offer_two], but continue with `T` if no message arrives within `timeout`.
+    fn offer_two_timeout<M1, M2, A1, A2, T>(
+        &mut self,
+        _o: Timed<OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action;
+
+    /// Like [SessionTypedChannel::offer_n], but continue with `T` if no message arrives within `timeout`.
+    fn offer_n_timeout<C, T>(
+        &mut self,
+        _o: Timed<OfferN<R2, C>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<C, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: Choice,
+        T: Action;
+
+    /// Enter the loop named by `T`. No communication takes place.
+    fn enter<T>(&mut self, _o: Rec<T>) -> Result<T::Body, SessionError>
+    where
+        T: RecursionVariable,
+    {
+        Ok(T::Body::new(Token::new()))
+    }
+
+    /// Jump back to the start of the loop named by `T`. No communication takes place.
+    fn recurse<T>(&mut self, _o: Var<T>) -> Result<T::Body, SessionError>
+    where
+        T: RecursionVariable,
+    {
+        Ok(T::Body::new(Token::new()))
+    }
+
+    fn close(self, end: End) -> Result<(), SessionError>;
+}
+
+/// [SessionGuard] tracks the session running on a channel.
+/// A channel that is dropped after its session started but before it reached [End]
+/// leaves the peer waiting for actions that never come, so the guard reports it on standard error.
+/// Drops while panicking are not reported, the panic already says what went wrong.
+pub(crate) struct SessionGuard {
+    channel: &'static str,
+    state: SessionState,
+}
+
+#[derive(PartialEq, Eq)]
+enum SessionState {
+    Idle,
+    Running,
+    Closed,
+    Delegated,
+}
+
+impl SessionGuard {
+    pub(crate) fn new<C>() -> Self {
+        SessionGuard {
+            channel: std::any::type_name::<C>(),
+            state: SessionState::Idle,
+        }
+    }
+
+    pub(crate) fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        if self.state != SessionState::Idle {
+            return Err(SessionError::AlreadyStarted);
+        }
+        self.state = SessionState::Running;
+        Ok(A::new(Token::new()))
+    }
+
+    /// A guard whose session of local type `A` is started right away, with its first action.
+    pub(crate) fn started<C, A>() -> (Self, A)
+    where
+        A: Action,
+    {
+        let guard = SessionGuard {
+            channel: std::any::type_name::<C>(),
+            state: SessionState::Running,
+        };
+        (guard, A::new(Token::new()))
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.state = SessionState::Closed;
+    }
+
+    pub(crate) fn delegate(&mut self) {
+        self.state = SessionState::Delegated;
+    }
 }
 
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if self.state == SessionState::Running && !thread::panicking() {
+            eprintln!("{}: session dropped before reaching End", self.channel);
+        }
+    }
+}
+
+/// [session_type] declares session types in the syntax of `tcp.ctx`, see [tcp_st_macros::session_type].
+/// A `dual` declaration names the [Dual] of the local type, the local type of its peer,
+/// with its own enums and markers:
+///
+/// ```
+/// use tcp_st::{
+///     crossbeam::{Close, Connected, Open},
+///     session_type, Dual, End, OfferOne, Rec, RecursionVariable, Role, SelectN, Var,
+/// };
+///
+/// struct RoleServerSystem;
+/// impl Role for RoleServerSystem {}
+/// struct RoleServerUser;
+/// impl Role for RoleServerUser {}
+///
+/// session_type! {
+///     type User = server_system<+>open(Open).mu(t)(
+///         server_system&{connected(Connected).t, close(Close).end}
+///     );
+///     dual type System for server_user;
+/// }
+///
+/// fn dual<A: Dual<RoleServerUser, Output = B>, B>() {}
+/// fn body<T: RecursionVariable<Body = B>, B>() {}
+/// dual::<User, OfferOne<RoleServerUser, Open, Rec<SystemT>>>();
+/// body::<SystemT, SelectN<RoleServerUser, SystemChoice0>>();
+/// let _ = |choice: SystemChoice0| match choice {
+///     SystemChoice0::Connected(Connected {}, next) => drop::<Var<SystemT>>(next),
+///     SystemChoice0::Close(Close {}, end) => drop::<End>(end),
+/// };
+/// ```
+///
+/// A recursion variable must be bound by a `mu`:
+///
+/// ```compile_fail
+/// # use tcp_st::{crossbeam::{Close, Connected, Open}, session_type, Role};
+/// # struct RoleServerSystem;
+/// # impl Role for RoleServerSystem {}
+/// # struct RoleClientSystem;
+/// # impl Role for RoleClientSystem {}
+/// session_type! {
+///     type User = server_system<+>open(Open).mu(t)(
+///         server_system&{connected(Connected).s, close(Close).end}
+///     );
+/// }
+/// ```
+///
+/// Labels must be distinct variants, `connected_` would be a second `Connected`:
+///
+/// ```compile_fail
+/// # use tcp_st::{crossbeam::{Close, Connected, Open}, session_type, Role};
+/// # struct RoleServerSystem;
+/// # impl Role for RoleServerSystem {}
+/// # struct RoleClientSystem;
+/// # impl Role for RoleClientSystem {}
+/// session_type! {
+///     type User = server_system<+>open(Open).mu(t)(
+///         server_system&{connected(Connected).t, connected_(Close).end}
+///     );
+/// }
+/// ```
+///
+/// And a local type only has a dual if all its actions are with the same peer:
+///
+/// ```compile_fail
+/// # use tcp_st::{crossbeam::{Close, Connected, Open}, session_type, Role};
+/// # struct RoleServerSystem;
+/// # impl Role for RoleServerSystem {}
+/// # struct RoleClientSystem;
+/// # impl Role for RoleClientSystem {}
+/// session_type! {
+///     type User = server_system<+>open(Open).client_system&connected(Connected).end;
+///     dual type System for server_user;
+/// }
+/// ```
+///
+/// which it does not need otherwise:
+///
+/// ```
+/// # use tcp_st::{crossbeam::{Close, Connected, Open}, session_type, Role};
+/// # struct RoleServerSystem;
+/// # impl Role for RoleServerSystem {}
+/// # struct RoleClientSystem;
+/// # impl Role for RoleClientSystem {}
+/// session_type! {
+///     type User = server_system<+>open(Open).client_system&connected(Connected).end;
+/// }
+/// ```
+pub use tcp_st_macros::session_type;
+pub use tcp_st_model as model;
+
+// Code generated for this crate refers to it by name, as it would from any other crate.
+extern crate self as tcp_st;
+
 pub mod crossbeam;
+pub mod describe;
+pub mod isn;
+pub mod monitor;
+pub mod multiparty;
 pub mod net_channel;
+pub mod pcap;
+pub mod retransmission;
+pub mod sequence;
+pub mod simulated;
+pub mod tcb;
+
+/// Messages for the payload sorts of the model in `tcp.ctx`, generated by the build script.
+/// The segment sorts such as `SegSynSet` are the validated messages of [net_channel],
+/// the other messages hold their packet as is.
+pub mod sorts {
+    include!(concat!(env!("OUT_DIR"), "/sorts.rs"));
+}
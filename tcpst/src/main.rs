@@ -3,6 +3,18 @@
 #![warn(clippy::restriction)]
 #![warn(clippy::nursery)]
 #![warn(clippy::cargo)]
+#![allow(
+    clippy::blanket_clippy_restriction_lints,
+    reason = "the restriction lints are enabled as a whole on purpose, conflicting ones are allowed where they apply"
+)]
+#![allow(
+    clippy::cargo_common_metadata,
+    reason = "the crates of the workspace are not published"
+)]
+#![allow(
+    clippy::multiple_crate_versions,
+    reason = "pnet and raw-socket depend on different versions of socket2"
+)]
 
 /**
  * Copyright 2023, Ivan Nikitin.
@@ -17,37 +29,65 @@
  * If not, see <https://www.gnu.org/licenses/>.
  *
  */
-use std::net::Ipv4Addr;
-use std::ops::Add;
+use core::net::{Ipv4Addr, SocketAddrV4};
 use std::thread;
 
-use crossbeam_channel::unbounded;
 use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::Packet;
 use tcp_st::crossbeam::{Close, Connected, CrossBeamRoleChannel, Open, TcbCreated};
-use tcp_st::net_channel::{Ack, FinAck, NetChannel, Syn, SynAck};
-use tcp_st::{Action, End, OfferOne, Role, SelectOne, SessionTypedChannel};
-
-use pnet::packet::tcp::{ipv4_checksum, MutableTcpPacket, TcpFlags, TcpPacket};
+use tcp_st::isn::Rfc6528;
+use tcp_st::multiparty::MultipartyChannel;
+use tcp_st::net_channel::{NetChannel, SERVER_PORT};
+use tcp_st::sorts::{SegAckSet, SegFinSet, SegSynAckSet, SegSynSet};
+use tcp_st::tcb::Tcb;
+use tcp_st::{Role, SessionError, SessionTypedChannel as _};
+
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
 use pnet::transport::tcp_packet_iter;
 use pnet::transport::transport_channel;
 use pnet::transport::TransportChannelType::Layer4;
 use pnet::transport::TransportProtocol::Ipv4;
 use raw_socket::{Domain, Protocol, Type};
 
-pub struct RoleServerSystem;
+/// The role of the TCP server.
+struct RoleServerSystem;
 impl Role for RoleServerSystem {}
 
-pub struct RoleServerUser;
+/// The role of the user of the TCP server.
+struct RoleServerUser;
 impl Role for RoleServerUser {}
 
-pub struct RoleServerClient;
-impl Role for RoleServerClient {}
-
+/// The role of the remote TCP client.
+struct RoleClientSystem;
+impl Role for RoleClientSystem {}
+
+// The session types of the local views of the TCP server and user,
+// projected from `tcp_handshake.global` by the build script:
+// `ServerSystemSessionType` is the view of the TCP server, `ServerUserSessionType`
+// the view of the TCP user, and `ServerSystemUserView` the actions of the TCP server
+// that involve the user. The latter must be dual to the view of the TCP user,
+// which is checked when the channel between the two is created.
+include!(concat!(env!("OUT_DIR"), "/handshake.rs"));
+
+#[expect(
+    clippy::question_mark_used,
+    clippy::shadow_reuse,
+    clippy::implicit_return,
+    reason = "each step of a session consumes the continuation `cont`, rebinds it and fails the session with `?`"
+)]
+#[expect(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    reason = "the server cannot run without its raw sockets, nor continue once the segments of the client are malformed"
+)]
+#[expect(
+    clippy::print_stderr,
+    reason = "the failure of a session is reported to whoever runs the server"
+)]
 fn main() {
-    let remote_addr = Ipv4Addr::new(127, 0, 0, 1);
-    let local_addr = Ipv4Addr::new(127, 0, 0, 1);
-    let source_port = 49155u16;
+    let remote_addr = Ipv4Addr::LOCALHOST;
+    let local_addr = Ipv4Addr::LOCALHOST;
+    let source_port = SERVER_PORT;
 
     // Silly trick to make the kernel not process TCP packets
     // this is used in combination with `iptables -A OUTPUT -p tcp --tcp-flags RST RST -j DROP`,
@@ -64,163 +104,111 @@ fn main() {
     let protocol = Layer4(Ipv4(IpNextHeaderProtocols::Tcp));
     let (tx, mut rx) = match transport_channel(4096, protocol) {
         Ok((tx, rx)) => (tx, rx),
-        Err(e) => panic!(
-            "An error occurred when creating the transport channel: {}",
-            e
-        ),
+        Err(error) => panic!("An error occurred when creating the transport channel: {error}"),
     };
     let iter = tcp_packet_iter(&mut rx);
-    let mut net_channel =
-        NetChannel::<RoleServerSystem, RoleServerClient>::new(iter, tx, remote_addr);
-
-    // Create the underlying communication channel and the session typed CrossbeamChannel
-    let (cbtx1, cbrx1) = unbounded();
-    let (cbtx2, cbrx2) = unbounded();
-    let mut system_user_channel =
-        CrossBeamRoleChannel::<RoleServerSystem, RoleServerUser>::new(cbtx2, cbrx1);
-    let mut user_system_channel =
-        CrossBeamRoleChannel::<RoleServerUser, RoleServerSystem>::new(cbtx1, cbrx2);
-
-    // Create and instantiate the session type of the local view of the TCP server.
-    type ServerSystemSessionType = OfferOne<
-        RoleServerUser,
-        Open,
-        SelectOne<
-            RoleServerUser,
-            TcbCreated,
-            OfferOne<
-                RoleServerClient,
-                Syn,
-                SelectOne<
-                    RoleServerClient,
-                    SynAck,
-                    OfferOne<
-                        RoleServerClient,
-                        Ack,
-                        SelectOne<
-                            RoleServerUser,
-                            Connected,
-                            OfferOne<
-                                RoleServerUser,
-                                Close,
-                                SelectOne<
-                                    RoleServerClient,
-                                    FinAck,
-                                    SelectOne<RoleServerUser, Close, End>,
-                                >,
-                            >,
-                        >,
-                    >,
-                >,
-            >,
-        >,
-    >;
-    let st_system_server = ServerSystemSessionType::new();
-
-    // Create and instantiate the session type of the local view of the TCP user.
-    type ServerUserSessionType = SelectOne<
-        RoleServerSystem,
-        Open,
-        OfferOne<
-            RoleServerSystem,
-            TcbCreated,
-            OfferOne<
-                RoleServerSystem,
-                Connected,
-                SelectOne<RoleServerSystem, Close, OfferOne<RoleServerSystem, Close, End>>,
-            >,
-        >,
-    >;
-    let st_sustem_user = ServerUserSessionType::new();
+    // The kernel does not compute checksums on the loopback interface,
+    // so the segments netcat sends us never carry a valid one.
+    let net_channel =
+        NetChannel::<RoleServerSystem, RoleClientSystem>::new(iter, tx, local_addr, remote_addr)
+            .without_checksum_verification();
+
+    // Create the session typed CrossbeamChannel between the TCP system and the user,
+    // with the session of the user started. The view of the TCP system is not followed
+    // on its own, the multiparty session below drives the channel, and it is projected
+    // from the same global type as `ServerSystemSessionType` by the build script.
+    let ((system_user_channel, _), (mut user_system_channel, st_user)) =
+        CrossBeamRoleChannel::<RoleServerSystem, RoleServerUser>::pair::<
+            ServerSystemUserView,
+            ServerUserSessionType,
+        >();
+
+    // The TCP system talks to both the remote client and the user,
+    // each action of its session type is routed to the channel of the role it names.
+    let mut system_channel = MultipartyChannel::<RoleServerSystem, _>::new()
+        .with(net_channel)
+        .with(system_user_channel);
+
+    // The initial sequence numbers of the connections, as in RFC 6528.
+    let mut isn = Rfc6528::new();
 
     thread::scope(|scope| {
-        let thread_a = scope.spawn(|| {
+        let thread_a = scope.spawn(move || -> Result<(), SessionError> {
             // Thread A simulates the kind of calls the userspace would send to the TCP system.
             // These are not actually implemented but it demonstrates the user of another
             // session typed channel on a different medium.
             // This also allows us to demonstrate the TCP system communicating with two sepparate participants.
 
-            let cont = user_system_channel.select_one(st_sustem_user, Open {});
-            let (_, cont) = user_system_channel.offer_one(cont);
-            let (_, cont) = user_system_channel.offer_one(cont);
-            let cont = user_system_channel.select_one(cont, Close {});
-            let (_, cont) = user_system_channel.offer_one(cont);
-            user_system_channel.close(cont);
+            // The session of the local view of the user was started with the channel.
+            let cont = user_system_channel.select_one(st_user, Open {})?;
+            let (_, cont) = user_system_channel.offer_one(cont)?;
+            let (_, cont) = user_system_channel.offer_one(cont)?;
+            let cont = user_system_channel.select_one(cont, Close {})?;
+            let (_, cont) = user_system_channel.offer_one(cont)?;
+            user_system_channel.close(cont)
         });
-        let thread_b = scope.spawn(|| {
+        let thread_b = scope.spawn(move || -> Result<(), SessionError> {
             // Thread B shows the communication from the point of the TCP system.
             // TCP system communicates with both the remote client and the local userspace.
-            // The example only demonstrates establishing a handshake and then sending the closing packet.
+            // The example only demonstrates establishing a handshake and then sending the closing packet
+            // once the client sent its first segment, as the server of `tcp.ctx` does.
+
+            // Start the session of the local view of the TCP server.
+            let st_system_server = system_channel.start::<ServerSystemSessionType>()?;
 
             // Recieve the OPEN call from the user
-            let (_, cont) = system_user_channel.offer_one(st_system_server);
+            let (_, cont) = system_channel.offer_one(st_system_server)?;
             // Notify the user that the we are ready to accept an incoming connection
-            let cont = system_user_channel.select_one(cont, TcbCreated {});
+            let cont = system_channel.select_one(cont, TcbCreated {})?;
 
             // Recieve a SYN packet indicating the beginning of the opening handshake.
-            let (syn_message, cont) = net_channel.offer_one(cont);
-
-            // Construct a SYN-ACK packet and cast it to the appropriate message type.
-            let packet = TcpPacket::new(&syn_message.packet).unwrap();
-            let mut vec: Vec<u8> = vec![0; syn_message.packet.len()];
-            let mut new_packet = MutableTcpPacket::new(&mut vec).unwrap();
-            new_packet.set_flags(TcpFlags::ACK | TcpFlags::SYN);
-            new_packet.set_sequence(1);
-            new_packet.set_acknowledgement(packet.get_sequence().add(1));
-            new_packet.set_source(packet.get_destination());
-            new_packet.set_destination(packet.get_source());
-            new_packet.set_window(packet.get_window());
-            new_packet.set_data_offset(packet.get_data_offset());
-            let checksum = ipv4_checksum(&new_packet.to_immutable(), &local_addr, &remote_addr);
-            new_packet.set_checksum(checksum);
-            let new_packet_slice = new_packet.packet();
-
-            // Send the message along the channel, following our session type.
-            let cont = net_channel.select_one(
-                cont,
-                SynAck {
-                    packet: new_packet_slice.to_vec(),
-                },
+            let (syn_message, cont) = system_channel.offer_one(cont)?;
+
+            // The SYN names the remote socket, create the TCB of the connection.
+            // From now on the channel to the client checks and accounts for every
+            // segment received against the TCB, and the segments are built from it.
+            let syn = TcpPacket::new(&syn_message.packet).unwrap();
+            let mut tcb = Tcb::open(
+                SocketAddrV4::new(local_addr, source_port),
+                SocketAddrV4::new(remote_addr, syn.get_source()),
+                &mut isn,
             );
+            tcb.receive(&syn_message.packet);
+            let packet = tcb.segment(TcpFlags::SYN | TcpFlags::ACK, &[]);
+            system_channel.channel::<RoleClientSystem, _>().set_tcb(tcb);
+
+            // Send the SYN-ACK along the channel, following our session type.
+            let cont = system_channel.select_one(cont, SegSynAckSet { packet })?;
 
             // Recieve a message of type ACK.
-            let (ack_message, cont) = net_channel.offer_one(cont);
-            let packet = TcpPacket::new(&ack_message.packet).unwrap();
+            let (_, cont) = system_channel.offer_one(cont)?;
 
             // Notify the user that the connection was established.
-            let cont = system_user_channel.select_one(cont, Connected {});
+            let cont = system_channel.select_one(cont, Connected {})?;
+            // Recieve the first segment of the client, whose data is not read.
+            let (_, cont) = system_channel.offer_one(cont)?;
             // Recieve the CLOSE call from the user
-            let (_, cont) = system_user_channel.offer_one(cont);
-
-            // For this example we will always just respond with a FIN-ACK and end.
-            let mut vec: Vec<u8> = vec![0; packet.packet().len()];
-            let mut new_packet = MutableTcpPacket::new(&mut vec).unwrap();
-            new_packet.set_flags(TcpFlags::ACK | TcpFlags::FIN);
-            new_packet.set_sequence(packet.get_acknowledgement());
-            new_packet.set_acknowledgement(packet.get_sequence().add(1));
-            new_packet.set_source(packet.get_destination());
-            new_packet.set_destination(packet.get_source());
-            new_packet.set_window(packet.get_window());
-            new_packet.set_data_offset(packet.get_data_offset());
-            let checksum = ipv4_checksum(&new_packet.to_immutable(), &local_addr, &remote_addr);
-            new_packet.set_checksum(checksum);
-            let new_packet_slice = new_packet.packet();
-
-            // Send the FIN-ACK along the channel.
-            let cont = net_channel.select_one(
-                cont,
-                FinAck {
-                    packet: new_packet_slice.to_vec(),
-                },
-            );
+            let (_, cont) = system_channel.offer_one(cont)?;
+
+            // For this example we will always just close the connection with a FIN and end.
+            let fin = system_channel
+                .channel::<RoleClientSystem, _>()
+                .tcb()
+                .expect("the TCB was created with the SYN")
+                .segment(TcpFlags::FIN | TcpFlags::ACK, &[]);
+            let cont = system_channel.select_one(cont, SegFinSet { packet: fin })?;
 
             // Notify the user that the connection was closed.
-            let cont = system_user_channel.select_one(cont, Close {});
+            let cont = system_channel.select_one(cont, Close {})?;
 
-            // End the session-typed communication, whichs drops the channel.
-            net_channel.close(cont);
+            // End the session-typed communication, whichs drops the channels.
+            system_channel.close(cont)
         });
-        thread_a.join().unwrap();
-        thread_b.join().unwrap();
+        if let Err(error) = thread_a.join().unwrap() {
+            eprintln!("The user session failed: {error}");
+        }
+        if let Err(error) = thread_b.join().unwrap() {
+            eprintln!("The system session failed: {error}");
+        }
     });
 }
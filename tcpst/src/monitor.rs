@@ -0,0 +1,634 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{collections::BTreeSet, fmt, marker::PhantomData, time::Duration};
+
+use tcp_st_model::{
+    ctx::Context,
+    export::{Automaton, Direction},
+    ProjectionError,
+};
+
+use crate::{
+    describe, Action, Branch, Choice, End, Message, OfferN, OfferOne, OfferTwo, OfferedTwo, Role,
+    SelectN, SelectOne, SelectTwo, SessionError, SessionTypedChannel, Timed, TimedBranch,
+    TimedOfferedTwo, Token,
+};
+
+/// [Violation] is a message, or the end of a session, that the automaton of the monitor
+/// does not allow in any of its current states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The role of the automaton.
+    pub role: String,
+    /// The states the automaton was in.
+    pub states: Vec<usize>,
+    /// The offending action, `peer!label(sort)`, `peer?label(sort)` or `end`,
+    /// with `_` for a label or sort that the Rust type does not name.
+    pub action: String,
+    /// The actions the automaton allows in `states`.
+    pub expected: Vec<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in state {:?} cannot do {}, expected ",
+            self.role, self.states, self.action
+        )?;
+        if self.expected.is_empty() {
+            write!(f, "nothing")
+        } else {
+            write!(f, "one of {}", self.expected.join(", "))
+        }
+    }
+}
+
+/// [RawFrame] is a received frame before it is decoded into a message,
+/// with what its channel can tell of it from the wire.
+pub struct RawFrame {
+    /// The index of the branch of the offer that the frame names, if the channel carries
+    /// the branch on the wire or the frame stands for an event of the channel itself,
+    /// e.g. the retransmission timer of [crate::net_channel::NetChannel].
+    pub label: Option<usize>,
+    /// The payload sort of the frame, if the channel carries or classifies sorts.
+    pub sort: Option<&'static str>,
+    pub packet: Vec<u8>,
+}
+
+/// [RawChannel] is implemented by the channels whose frames a [Monitor] can check
+/// before they are decoded. A frame the model does not allow thus fails the offer with
+/// [SessionError::Violation], instead of a decoding error or, on channels that skip
+/// the frames that match no branch, not at all.
+pub trait RawChannel {
+    /// Receive the next frame for the session, or [None] if `timeout` passes first.
+    /// `labels` are the labels of the offer, which the channel names its own events with.
+    fn receive_raw(
+        &mut self,
+        timeout: Option<Duration>,
+        labels: &[&str],
+    ) -> Result<Option<RawFrame>, SessionError>;
+}
+
+/// [Observed] is an action of the channel as the monitor sees it.
+/// The label is only known for n-ary choices, whose Rust types name their labels,
+/// and the sort of a received frame is the one it carries, if any, or that of its branch.
+struct Observed<'a> {
+    direction: Direction,
+    peer: &'a str,
+    label: Option<String>,
+    sort: Option<&'static str>,
+}
+
+impl fmt::Display for Observed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Send => '!',
+            Direction::Receive => '?',
+        };
+        write!(
+            f,
+            "{}{direction}{}({})",
+            self.peer,
+            self.label.as_deref().unwrap_or("_"),
+            self.sort.unwrap_or("_")
+        )
+    }
+}
+
+/// [Monitor] wraps the channel from role `R1` to role `R2` and checks every message at runtime
+/// against the automaton of the local type of `R1` in the model, e.g. its entry in `tcp.ctx`.
+/// Messages are matched by their [Message::sort] and, for n-ary choices, by their label.
+/// A message is checked before it is sent, and when it is received before it is decoded,
+/// from the label and sort of its frame, see [RawChannel]. A message the automaton does not
+/// allow fails the operation with [SessionError::Violation], which includes a received frame
+/// that is none of the messages of the offer.
+///
+/// The automaton is followed as a nondeterministic one, which keeps every state
+/// that agrees with the messages so far, so branches that differ only in their label
+/// are told apart as soon as the session does.
+pub struct Monitor<R1, R2, C>
+where
+    R1: Role,
+    R2: Role,
+{
+    channel: C,
+    automaton: Automaton,
+    states: BTreeSet<usize>,
+    peer: String,
+    phantom: PhantomData<(R1, R2)>,
+}
+
+impl<R1, R2, C> Monitor<R1, R2, C>
+where
+    R1: Role,
+    R2: Role,
+    C: SessionTypedChannel<R1, R2> + RawChannel,
+{
+    /// Monitor `channel` with `automaton`, which should be the local type of `R1` restricted to `R2`:
+    /// every message of `channel` is checked against it, and transitions to other peers never match.
+    pub fn new(channel: C, automaton: Automaton) -> Self {
+        Monitor {
+            channel,
+            automaton,
+            states: BTreeSet::from([0]),
+            peer: describe::role::<R2>(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Monitor `channel` with the local type of `R1` in `context` restricted to `R2`.
+    /// Roles are named as in [describe::describe], e.g. `RoleServerSystem` is `server_system`.
+    pub fn from_context(channel: C, context: &Context) -> Result<Self, ProjectionError> {
+        let role = describe::role::<R1>();
+        let entry = context.entry(&role).ok_or_else(|| ProjectionError {
+            role: role.clone(),
+            message: "the context has no entry for the role".to_string(),
+        })?;
+        let local = entry.local.to_local().restrict(&describe::role::<R2>())?;
+        Ok(Self::new(channel, Automaton::new(&role, &local)))
+    }
+
+    /// The states the automaton is in.
+    pub fn states(&self) -> impl Iterator<Item = usize> + '_ {
+        self.states.iter().copied()
+    }
+
+    /// The wrapped channel.
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+
+    /// Move the automaton along `observed`, or fail without moving it.
+    fn step(&mut self, observed: Observed) -> Result<(), SessionError> {
+        let next: BTreeSet<usize> = self
+            .automaton
+            .transitions
+            .iter()
+            .filter(|t| {
+                self.states.contains(&t.source)
+                    && t.direction == observed.direction
+                    && t.peer == observed.peer
+                    && observed.label.as_ref().is_none_or(|l| *l == t.label)
+                    && observed.sort.is_none_or(|s| s == t.sort)
+            })
+            .map(|t| t.target)
+            .collect();
+        if next.is_empty() {
+            return Err(self.violation(observed.to_string()));
+        }
+        self.states = next;
+        Ok(())
+    }
+
+    fn send<M>(&mut self, label: Option<String>) -> Result<(), SessionError>
+    where
+        M: Message,
+    {
+        let peer = self.peer.clone();
+        self.step(Observed {
+            direction: Direction::Send,
+            peer: &peer,
+            label,
+            sort: Some(M::sort()),
+        })
+    }
+
+    /// Receive the next frame and check it before it is decoded. The frame is the branch
+    /// it names, or else the first branch whose sort it has and whose message it matches,
+    /// as `branch` finds it, one of `branches`. It must be of the sort of the branch, if the
+    /// branch has one in `sorts`, and the automaton must allow it with the label of the branch
+    /// in `labels`.
+    /// Returns the branch and the packet to decode, or [None] if `timeout` passes first.
+    fn receive(
+        &mut self,
+        timeout: Option<Duration>,
+        labels: &[&str],
+        branches: usize,
+        sorts: &[&'static str],
+        branch: impl FnOnce(&RawFrame) -> Option<usize>,
+    ) -> Result<Option<(usize, Vec<u8>)>, SessionError> {
+        let Some(frame) = self.channel.receive_raw(timeout, labels)? else {
+            return Ok(None);
+        };
+        let peer = self.peer.clone();
+        let index = frame.label.or_else(|| branch(&frame));
+        let sort = index.and_then(|index| sorts.get(index).copied());
+        let observed = Observed {
+            direction: Direction::Receive,
+            peer: &peer,
+            label: index
+                .and_then(|index| labels.get(index))
+                .map(|label| describe::snake_case(label)),
+            sort: frame.sort.or(sort),
+        };
+        let Some(index) = index.filter(|index| *index < branches) else {
+            return Err(self.violation(observed.to_string()));
+        };
+        if frame
+            .sort
+            .zip(sort)
+            .is_some_and(|(found, expected)| found != expected)
+        {
+            return Err(self.violation(observed.to_string()));
+        }
+        self.step(observed)?;
+        Ok(Some((index, frame.packet)))
+    }
+
+    /// Receive the message of an offer of `M`, see [Monitor::receive].
+    fn receive_one<M>(&mut self, timeout: Option<Duration>) -> Result<Option<M>, SessionError>
+    where
+        M: Message,
+    {
+        // outside of a choice a frame names no branch, or the only one
+        let received = self.receive(timeout, &[], 1, &[M::sort()], |frame| {
+            frame
+                .sort
+                .is_none_or(|sort| sort == M::sort() && M::matches(&frame.packet))
+                .then_some(0)
+        })?;
+        match received {
+            Some((_, packet)) => Ok(Some(M::from_net_representation(packet)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Receive the branch of an offer of `M1` or `M2`, see [Monitor::receive].
+    fn receive_two<M1, M2, A1, A2>(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<OfferedTwo<M1, M2, A1, A2>>, SessionError>
+    where
+        M1: Message,
+        M2: Message,
+        A1: Action,
+        A2: Action,
+    {
+        let received = self.receive(timeout, &[], 2, &[M1::sort(), M2::sort()], |frame| {
+            let is = |sort: &str, matches: fn(&[u8]) -> bool| {
+                frame.sort == Some(sort) && matches(&frame.packet)
+            };
+            if is(M1::sort(), M1::matches) {
+                Some(0)
+            } else if is(M2::sort(), M2::matches) {
+                Some(1)
+            } else {
+                None
+            }
+        })?;
+        Ok(match received {
+            Some((0, packet)) => Some(Branch::Left((
+                M1::from_net_representation(packet)?,
+                A1::new(Token::new()),
+            ))),
+            Some((_, packet)) => Some(Branch::Right((
+                M2::from_net_representation(packet)?,
+                A2::new(Token::new()),
+            ))),
+            None => None,
+        })
+    }
+
+    /// Receive the branch of an n-ary offer of `Ch`, see [Monitor::receive].
+    /// The sorts of its branches are not known, the automaton checks the sort of the frame.
+    fn receive_n<Ch>(&mut self, timeout: Option<Duration>) -> Result<Option<Ch>, SessionError>
+    where
+        Ch: Choice,
+    {
+        let received = self.receive(timeout, Ch::LABELS, Ch::LABELS.len(), &[], |frame| {
+            frame
+                .sort
+                .and_then(|sort| Ch::label_of_sort(&frame.packet, sort))
+        })?;
+        match received {
+            Some((label, packet)) => Ok(Some(Ch::from_net_representation(
+                label,
+                packet,
+                Token::new(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn violation(&self, action: String) -> SessionError {
+        let expected = self
+            .automaton
+            .transitions
+            .iter()
+            .filter(|t| self.states.contains(&t.source))
+            .map(|t| t.to_string())
+            .collect::<BTreeSet<_>>();
+        SessionError::Violation(Violation {
+            role: self.automaton.role.clone(),
+            states: self.states.iter().copied().collect(),
+            action,
+            expected: expected.into_iter().collect(),
+        })
+    }
+}
+
+/// The label of the branch of the n-ary choice `choice`, as in [describe::choice_branch].
+fn label<C>(choice: &C) -> String
+where
+    C: Choice,
+{
+    describe::snake_case(C::LABELS[choice.label()])
+}
+
+impl<R1, R2, C> SessionTypedChannel<R1, R2> for Monitor<R1, R2, C>
+where
+    R1: Role,
+    R2: Role,
+    C: SessionTypedChannel<R1, R2> + RawChannel,
+{
+    fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        self.channel.start()
+    }
+
+    fn delegate(&mut self, token: Token) {
+        self.channel.delegate(token);
+    }
+
+    fn offer_one<M, A>(&mut self, _o: OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+    {
+        match self.receive_one(None)? {
+            Some(message) => Ok((message, A::new(Token::new()))),
+            None => Err(SessionError::Timeout),
+        }
+    }
+
+    fn select_one<M, A>(&mut self, o: SelectOne<R2, M, A>, message: M) -> Result<A, SessionError>
+    where
+        M: Message,
+        A: Action,
+    {
+        self.send::<M>(None)?;
+        self.channel.select_one(o, message)
+    }
+
+    fn offer_two<M1, M2, A1, A2>(
+        &mut self,
+        _o: OfferTwo<R2, M1, M2, A1, A2>,
+    ) -> Result<OfferedTwo<M1, M2, A1, A2>, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        self.receive_two(None)?.ok_or(SessionError::Timeout)
+    }
+
+    fn select_left<M1, M2, A1, A2>(
+        &mut self,
+        o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M1,
+    ) -> Result<A1, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        self.send::<M1>(None)?;
+        self.channel.select_left(o, message)
+    }
+
+    fn select_right<M1, M2, A1, A2>(
+        &mut self,
+        o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M2,
+    ) -> Result<A2, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        self.send::<M2>(None)?;
+        self.channel.select_right(o, message)
+    }
+
+    fn offer_n<Ch>(&mut self, _o: OfferN<R2, Ch>) -> Result<Ch, SessionError>
+    where
+        Ch: Choice,
+    {
+        self.receive_n(None)?.ok_or(SessionError::Timeout)
+    }
+
+    /// The label of the branch is only known once the branch is built, so the branch
+    /// is built, checked and taken apart again to hand the message on.
+    fn select_n<Ch, M, A>(
+        &mut self,
+        o: SelectN<R2, Ch>,
+        label_of: fn(M, A) -> Ch,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        Ch: Choice,
+        M: Message + 'static,
+        A: Action,
+    {
+        let choice = label_of(message, A::new(Token::new()));
+        self.send::<M>(Some(label(&choice)))?;
+        let branch = choice.label();
+        let message = choice
+            .into_message()
+            .downcast::<M>()
+            .map_err(|_| SessionError::UnexpectedLabel(branch))?;
+        self.channel.select_n(o, label_of, *message)
+    }
+
+    /// A timeout is local to `R1` and not part of the model, it leaves the automaton where it is.
+    fn offer_one_timeout<M, A, T>(
+        &mut self,
+        _o: Timed<OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+    {
+        Ok(match self.receive_one(Some(timeout))? {
+            Some(message) => TimedBranch::Received((message, A::new(Token::new()))),
+            None => TimedBranch::TimedOut(T::new(Token::new())),
+        })
+    }
+
+    fn offer_two_timeout<M1, M2, A1, A2, T>(
+        &mut self,
+        _o: Timed<OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action,
+    {
+        Ok(match self.receive_two(Some(timeout))? {
+            Some(branch) => TimedBranch::Received(branch),
+            None => TimedBranch::TimedOut(T::new(Token::new())),
+        })
+    }
+
+    fn offer_n_timeout<Ch, T>(
+        &mut self,
+        _o: Timed<OfferN<R2, Ch>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<Ch, T>, SessionError>
+    where
+        Ch: Choice,
+        T: Action,
+    {
+        Ok(match self.receive_n(Some(timeout))? {
+            Some(choice) => TimedBranch::Received(choice),
+            None => TimedBranch::TimedOut(T::new(Token::new())),
+        })
+    }
+
+    /// Fails with [SessionError::Violation] if the automaton is not in a final state,
+    /// after closing the wrapped channel.
+    fn close(self, end: End) -> Result<(), SessionError> {
+        let done = self
+            .states
+            .iter()
+            .any(|state| self.automaton.finals.contains(state));
+        let violation = (!done).then(|| self.violation("end".to_string()));
+        self.channel.close(end)?;
+        violation.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::unbounded;
+    use tcp_st_model::ctx::Context;
+
+    use super::Monitor;
+    use crate::{
+        crossbeam::{Close, CrossBeamRoleChannel, Open},
+        End, OfferN, OfferOne, Role, SelectN, SelectOne, SessionError, SessionTypedChannel,
+    };
+
+    struct RoleServerSystem;
+    impl Role for RoleServerSystem {}
+
+    struct RoleServerUser;
+    impl Role for RoleServerUser {}
+
+    crate::choice! {
+        enum Request {
+            Open(Open, End),
+            Close(Close, End),
+        }
+    }
+
+    // a branch labelled as the one the model allows, with the message of the other
+    crate::choice! {
+        enum Mislabelled {
+            Open(Close, End),
+        }
+    }
+
+    const CONTEXT: &str = "s[server_system]: server_user&open(Open).server_user<+>close(Close).end";
+    const CHOICE_CONTEXT: &str = "s[server_system]: server_user&{open(Open).end}";
+
+    type Channel = CrossBeamRoleChannel<RoleServerSystem, RoleServerUser>;
+    type Peer = CrossBeamRoleChannel<RoleServerUser, RoleServerSystem>;
+
+    fn monitored(context: &str) -> (Monitor<RoleServerSystem, RoleServerUser, Channel>, Peer) {
+        let (send, recv) = unbounded();
+        let (peer_send, peer_recv) = unbounded();
+        let context = Context::parse(context).unwrap();
+        let monitor = Monitor::from_context(Channel::new(send, peer_recv), &context).unwrap();
+        (monitor, Peer::new(peer_send, recv))
+    }
+
+    #[test]
+    fn a_session_that_follows_the_model_passes() {
+        let (mut monitor, mut peer) = monitored(CONTEXT);
+        let open = peer
+            .start::<SelectOne<RoleServerSystem, Open, OfferOne<RoleServerSystem, Close, End>>>()
+            .unwrap();
+        let close = peer.select_one(open, Open {}).unwrap();
+        let offer = monitor
+            .start::<OfferOne<RoleServerUser, Open, SelectOne<RoleServerUser, Close, End>>>()
+            .unwrap();
+        let (_, select) = monitor.offer_one(offer).unwrap();
+        let end = monitor.select_one(select, Close {}).unwrap();
+        monitor.close(end).unwrap();
+        let (_, end) = peer.offer_one(close).unwrap();
+        peer.close(end).unwrap();
+    }
+
+    #[test]
+    fn a_message_that_is_not_the_one_offered_is_a_violation() {
+        let (mut monitor, mut peer) = monitored(CONTEXT);
+        let close = peer
+            .start::<SelectOne<RoleServerSystem, Close, End>>()
+            .unwrap();
+        let end = peer.select_one(close, Close {}).unwrap();
+        peer.close(end).unwrap();
+
+        // `Close` decodes as an `Open` just as well, only its sort tells them apart
+        let offer = monitor
+            .start::<OfferOne<RoleServerUser, Open, SelectOne<RoleServerUser, Close, End>>>()
+            .unwrap();
+        let Err(SessionError::Violation(violation)) = monitor.offer_one(offer) else {
+            panic!("the monitor accepted a Close for an Open");
+        };
+        assert_eq!(violation.action, "server_user?_(Close)");
+        assert_eq!(violation.expected, ["server_user?open(Open)"]);
+    }
+
+    #[test]
+    fn a_branch_the_model_does_not_offer_is_a_violation() {
+        let (mut monitor, mut peer) = monitored(CHOICE_CONTEXT);
+        let select = peer.start::<SelectN<RoleServerSystem, Request>>().unwrap();
+        let end = peer.select_n(select, Request::Close, Close {}).unwrap();
+        peer.close(end).unwrap();
+
+        let offer = monitor.start::<OfferN<RoleServerUser, Request>>().unwrap();
+        let Err(SessionError::Violation(violation)) = monitor.offer_n(offer) else {
+            panic!("the monitor accepted a branch the model does not offer");
+        };
+        assert_eq!(violation.action, "server_user?close(Close)");
+    }
+
+    #[test]
+    fn a_branch_with_the_message_of_another_sort_is_a_violation() {
+        let (mut monitor, mut peer) = monitored(CHOICE_CONTEXT);
+        let select = peer
+            .start::<SelectN<RoleServerSystem, Mislabelled>>()
+            .unwrap();
+        let end = peer.select_n(select, Mislabelled::Open, Close {}).unwrap();
+        peer.close(end).unwrap();
+
+        let offer = monitor.start::<OfferN<RoleServerUser, Request>>().unwrap();
+        let Err(SessionError::Violation(violation)) = monitor.offer_n(offer) else {
+            panic!("the monitor accepted an open branch of sort Close");
+        };
+        assert_eq!(violation.action, "server_user?open(Close)");
+    }
+}
@@ -0,0 +1,311 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{marker::PhantomData, time::Duration};
+
+use crate::{
+    Action, Choice, End, Message, OfferN, OfferOne, OfferTwo, OfferedTwo, Rec, RecursionVariable,
+    Role, SelectN, SelectOne, SelectTwo, SessionError, SessionGuard, SessionTypedChannel, Timed,
+    TimedBranch, TimedOfferedTwo, Token, Var,
+};
+
+/// [Here] is the position of the first channel in the list of channels of a [MultipartyChannel].
+pub struct Here;
+
+/// [There] is the position of a channel after the first one, at position `I` in the rest of the list.
+pub struct There<I> {
+    phantom: PhantomData<I>,
+}
+
+/// [Route] finds the channel from role `R1` to role `R2` in a list of channels.
+/// The list is a nested tuple `(C1, (C2, (..., ())))` built by [MultipartyChannel::with],
+/// and `I` is the position of the channel in the list, which the compiler infers.
+/// If there is no channel to `R2` the list does not implement [Route] and any
+/// action towards `R2` fails to compile.
+pub trait Route<R1, R2, I>
+where
+    R1: Role,
+    R2: Role,
+{
+    type Channel: SessionTypedChannel<R1, R2>;
+
+    fn route(&mut self) -> &mut Self::Channel;
+}
+
+impl<R1, R2, C, T> Route<R1, R2, Here> for (C, T)
+where
+    R1: Role,
+    R2: Role,
+    C: SessionTypedChannel<R1, R2>,
+{
+    type Channel = C;
+
+    fn route(&mut self) -> &mut Self::Channel {
+        &mut self.0
+    }
+}
+
+impl<R1, R2, H, T, I> Route<R1, R2, There<I>> for (H, T)
+where
+    R1: Role,
+    R2: Role,
+    T: Route<R1, R2, I>,
+{
+    type Channel = T::Channel;
+
+    fn route(&mut self) -> &mut Self::Channel {
+        self.1.route()
+    }
+}
+
+/// [MultipartyChannel] is the endpoint of role `R1` in a multiparty session.
+/// It owns one binary [SessionTypedChannel] per peer role and routes each action
+/// to the channel of the role the action names, e.g. an `OfferOne<R2, M, A>`
+/// is received on the channel from `R1` to `R2`.
+/// This makes the role parameter of the session type binding: a local type that
+/// interleaves actions towards several peers can be followed with a single endpoint.
+pub struct MultipartyChannel<R1, P>
+where
+    R1: Role,
+{
+    peers: P,
+    guard: SessionGuard,
+    phantom: PhantomData<R1>,
+}
+
+impl<R1> MultipartyChannel<R1, ()>
+where
+    R1: Role,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        MultipartyChannel {
+            peers: (),
+            guard: SessionGuard::new::<Self>(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<R1> Default for MultipartyChannel<R1, ()>
+where
+    R1: Role,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R1, P> MultipartyChannel<R1, P>
+where
+    R1: Role,
+{
+    /// Add the channel to another peer role.
+    /// The multiparty session started with [MultipartyChannel::start] drives the sessions
+    /// of all peer channels, which are delegated to it and cannot be started on their own.
+    pub fn with<R2, C>(self, mut channel: C) -> MultipartyChannel<R1, (C, P)>
+    where
+        R2: Role,
+        C: SessionTypedChannel<R1, R2>,
+    {
+        channel.delegate(Token::new());
+        MultipartyChannel {
+            peers: (channel, self.peers),
+            guard: SessionGuard::new::<MultipartyChannel<R1, (C, P)>>(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The channel to the peer role `R2`, e.g. to reach state the channel keeps
+    /// outside of the session, such as the TCB of a [crate::net_channel::NetChannel].
+    /// Its session is delegated to the multiparty session, so it cannot be started.
+    pub fn channel<R2, I>(&mut self) -> &mut P::Channel
+    where
+        R2: Role,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route()
+    }
+
+    /// Start the multiparty session of local type `A`, see [SessionTypedChannel::start].
+    pub fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        self.guard.start()
+    }
+
+    pub fn offer_one<R2, M, A, I>(&mut self, o: OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
+    where
+        R2: Role,
+        M: Message + 'static,
+        A: Action + 'static,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_one(o)
+    }
+
+    pub fn select_one<R2, M, A, I>(
+        &mut self,
+        o: SelectOne<R2, M, A>,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        R2: Role,
+        M: Message,
+        A: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().select_one(o, message)
+    }
+
+    pub fn offer_two<R2, M1, M2, A1, A2, I>(
+        &mut self,
+        o: OfferTwo<R2, M1, M2, A1, A2>,
+    ) -> Result<OfferedTwo<M1, M2, A1, A2>, SessionError>
+    where
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_two(o)
+    }
+
+    pub fn select_left<R2, M1, M2, A1, A2, I>(
+        &mut self,
+        o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M1,
+    ) -> Result<A1, SessionError>
+    where
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().select_left(o, message)
+    }
+
+    pub fn select_right<R2, M1, M2, A1, A2, I>(
+        &mut self,
+        o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M2,
+    ) -> Result<A2, SessionError>
+    where
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().select_right(o, message)
+    }
+
+    pub fn offer_n<R2, C, I>(&mut self, o: OfferN<R2, C>) -> Result<C, SessionError>
+    where
+        R2: Role,
+        C: Choice,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_n(o)
+    }
+
+    pub fn select_n<R2, C, M, A, I>(
+        &mut self,
+        o: SelectN<R2, C>,
+        label: fn(M, A) -> C,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        R2: Role,
+        C: Choice,
+        M: Message + 'static,
+        A: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().select_n(o, label, message)
+    }
+
+    pub fn offer_one_timeout<R2, M, A, T, I>(
+        &mut self,
+        o: Timed<OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        R2: Role,
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_one_timeout(o, timeout)
+    }
+
+    pub fn offer_two_timeout<R2, M1, M2, A1, A2, T, I>(
+        &mut self,
+        o: Timed<OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_two_timeout(o, timeout)
+    }
+
+    pub fn offer_n_timeout<R2, C, T, I>(
+        &mut self,
+        o: Timed<OfferN<R2, C>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<C, T>, SessionError>
+    where
+        R2: Role,
+        C: Choice,
+        T: Action,
+        P: Route<R1, R2, I>,
+    {
+        self.peers.route().offer_n_timeout(o, timeout)
+    }
+
+    /// Enter the loop named by `T`. No communication takes place.
+    pub fn enter<T>(&mut self, _o: Rec<T>) -> Result<T::Body, SessionError>
+    where
+        T: RecursionVariable,
+    {
+        Ok(T::Body::new(Token::new()))
+    }
+
+    /// Jump back to the start of the loop named by `T`. No communication takes place.
+    pub fn recurse<T>(&mut self, _o: Var<T>) -> Result<T::Body, SessionError>
+    where
+        T: RecursionVariable,
+    {
+        Ok(T::Body::new(Token::new()))
+    }
+
+    /// End the session, which drops the channels to all peers.
+    pub fn close(mut self, _end: End) -> Result<(), SessionError> {
+        self.guard.close();
+        Ok(())
+    }
+}
@@ -12,16 +12,47 @@
  *
  */
 use pnet::{
-    packet::{tcp::MutableTcpPacket, Packet},
+    packet::{
+        tcp::{ipv4_checksum, MutableTcpPacket, TcpFlags, TcpPacket},
+        Packet,
+    },
     transport::{TcpTransportChannelIterator, TransportSender},
 };
 
-use crate::{Branch, Message, Role, SessionTypedChannel};
-use std::{marker::PhantomData, net::Ipv4Addr};
+use crate::{
+    monitor::{RawChannel, RawFrame},
+    retransmission::{Expiry, RetransmissionQueue, RETRY_THRESHOLD_EXCEEDED, RTO_EXCEEDED},
+    tcb::{Acceptability, Tcb},
+    Action, Branch, DecodeError, Message, OfferedTwo, Role, SessionError, SessionGuard,
+    SessionTypedChannel, Timed, TimedBranch, TimedOfferedTwo, Token,
+};
+use std::{
+    marker::PhantomData,
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant},
+};
+
+/// The port the example server listens on, and on which a [NetChannel] receives by default.
+pub const SERVER_PORT: u16 = 49155;
 
 /// [NetChannel] is a session-typed communication channel that uses
 /// libpnet [TransportSender] and [TcpTransportChannelIterator] under the hood.
 /// [NetChannel] behaves as any other session-typed channels and implements [SessionTypedChannel].
+///
+/// Once it holds the [Tcb] of the connection, see [NetChannel::set_tcb], the channel
+/// processes the receive window: every received segment is checked with [Tcb::acceptability],
+/// unacceptable segments are dropped and acknowledged, and acceptable ones are accounted
+/// for with [Tcb::receive] before they are offered. An offer thus only ever sees
+/// segments that passed the check, e.g. the `acceptable` branch of the data loop.
+/// Every offer, with one message or a choice of several, skips the segments that are
+/// none of its messages, see [Classify], or not of their sort, see [segment_sort].
+///
+/// The segments sent from then on that take sequence space are held in a
+/// [RetransmissionQueue] until they are acknowledged. When its timer expires during an
+/// offer with an `RtoExceeded` branch the channel takes that branch, with the segment to
+/// retransmit as its message, and once the segment was retransmitted too often it takes
+/// the `RetryThresholdExceeded` branch with the RST it sends. Offers without these branches
+/// retransmit on their own, and fail with [SessionError::RetryThresholdExceeded].
 pub struct NetChannel<'a, R1, R2>
 where
     R1: Role,
@@ -29,7 +60,13 @@ where
 {
     rx: TcpTransportChannelIterator<'a>,
     tx: TransportSender,
+    local_addr: Ipv4Addr,
     remote_addr: Ipv4Addr,
+    local_port: u16,
+    verify_checksum: bool,
+    tcb: Option<Tcb>,
+    retransmission: RetransmissionQueue,
+    guard: SessionGuard,
     phantom: PhantomData<(R1, R2)>,
 }
 
@@ -38,57 +75,38 @@ where
     R1: Role,
     R2: Role,
 {
-    #[must_use]
-    fn offer_one<M, A>(&mut self, _o: crate::OfferOne<R2, M, A>) -> (M, A)
+    fn offer_one<M, A>(&mut self, _o: crate::OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
     where
         M: crate::Message + 'static,
         A: crate::Action + 'static,
         R1: Role,
         R2: Role,
     {
-        loop {
-            match self.rx.next() {
-                Ok((packet, _)) => {
-                    // ignore packets that are not for us
-                    if packet.get_destination() != 49155 {
-                        continue;
-                    }
-                    let slice = packet.packet().to_vec();
-                    let message = M::from_net_representation(slice);
-                    return (message, A::new());
-                }
-                Err(e) => {
-                    panic!("An error occurred while reading: {e}");
-                }
-            }
+        match self.offer_one_until(None)? {
+            Some(message) => Ok((message, A::new(Token::new()))),
+            None => Err(SessionError::Timeout),
         }
     }
 
-    fn select_one<M, A>(&mut self, _o: crate::SelectOne<R2, M, A>, message: M) -> A
+    fn select_one<M, A>(
+        &mut self,
+        _o: crate::SelectOne<R2, M, A>,
+        message: M,
+    ) -> Result<A, SessionError>
     where
         M: crate::Message,
         A: crate::Action,
         R1: Role,
         R2: Role,
     {
-        let mut packet = message.to_net_representation();
-        let packet_inner = MutableTcpPacket::new(&mut packet[..]).unwrap();
-        match self
-            .tx
-            .send_to(packet_inner, std::net::IpAddr::V4(self.remote_addr))
-        {
-            Ok(_) => {
-                return A::new();
-            }
-            Err(e) => panic!("failed to send packet: {e}"),
-        }
+        self.send_segment(message.to_net_representation())?;
+        Ok(A::new(Token::new()))
     }
 
     fn offer_two<M1, M2, A1, A2>(
         &mut self,
         _o: crate::OfferTwo<R2, M1, M2, A1, A2>,
-        picker: Box<dyn Fn() -> bool>,
-    ) -> crate::Branch<(M1, A1), (M2, A2)>
+    ) -> Result<crate::OfferedTwo<M1, M2, A1, A2>, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -97,41 +115,9 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        let choice = picker();
-        match choice {
-            true => {
-                loop {
-                    match self.rx.next() {
-                        Ok((packet, _)) => {
-                            // ignore packets that are not for us
-                            if packet.get_destination() != 49155 {
-                                continue;
-                            }
-                            let slice = packet.packet().to_vec();
-                            let message = M1::from_net_representation(slice);
-                            return Branch::Left((message, A1::new()));
-                        }
-                        Err(e) => {
-                            panic!("An error occurred while reading: {e}");
-                        }
-                    }
-                }
-            }
-            false => loop {
-                match self.rx.next() {
-                    Ok((packet, _)) => {
-                        if packet.get_destination() != 49155 {
-                            continue;
-                        }
-                        let slice = packet.packet().to_vec();
-                        let message = M2::from_net_representation(slice);
-                        return Branch::Right((message, A2::new()));
-                    }
-                    Err(e) => {
-                        panic!("An error occurred while reading: {e}");
-                    }
-                }
-            },
+        match self.offer_two_until(None)? {
+            Some(branch) => Ok(branch),
+            None => Err(SessionError::Timeout),
         }
     }
 
@@ -139,7 +125,7 @@ where
         &mut self,
         _o: crate::SelectTwo<R2, M1, M2, A1, A2>,
         message: M1,
-    ) -> A1
+    ) -> Result<A1, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -148,24 +134,15 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        let mut packet = message.to_net_representation();
-        let packet_inner = MutableTcpPacket::new(&mut packet[..]).unwrap();
-        match self
-            .tx
-            .send_to(packet_inner, std::net::IpAddr::V4(self.remote_addr))
-        {
-            Ok(_) => {
-                return A1::new();
-            }
-            Err(e) => panic!("failed to send packet: {e}"),
-        }
+        self.send_segment(message.to_net_representation())?;
+        Ok(A1::new(Token::new()))
     }
 
     fn select_right<M1, M2, A1, A2>(
         &mut self,
         _o: crate::SelectTwo<R2, M1, M2, A1, A2>,
         message: M2,
-    ) -> A2
+    ) -> Result<A2, SessionError>
     where
         R1: Role,
         R2: Role,
@@ -174,21 +151,137 @@ where
         A1: crate::Action,
         A2: crate::Action,
     {
-        let mut packet = message.to_net_representation();
-        let packet_inner = MutableTcpPacket::new(&mut packet[..]).unwrap();
-        match self
-            .tx
-            .send_to(packet_inner, std::net::IpAddr::V4(self.remote_addr))
-        {
-            Ok(_) => {
-                return A2::new();
-            }
-            Err(e) => panic!("failed to send packet: {e}"),
+        self.send_segment(message.to_net_representation())?;
+        Ok(A2::new(Token::new()))
+    }
+
+    fn offer_n<C>(&mut self, _o: crate::OfferN<R2, C>) -> Result<C, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+    {
+        match self.offer_n_until(None)? {
+            Some(branch) => Ok(branch),
+            None => Err(SessionError::Timeout),
+        }
+    }
+
+    fn select_n<C, M, A>(
+        &mut self,
+        _o: crate::SelectN<R2, C>,
+        label: fn(M, A) -> C,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+        M: crate::Message + 'static,
+        A: crate::Action,
+    {
+        self.send_segment(label(message, A::new(Token::new())).to_net_representation())?;
+        Ok(A::new(Token::new()))
+    }
+
+    fn offer_one_timeout<M, A, T>(
+        &mut self,
+        _o: Timed<crate::OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+        R1: Role,
+        R2: Role,
+    {
+        match self.offer_one_until(Some(Instant::now() + timeout))? {
+            Some(message) => Ok(TimedBranch::Received((message, A::new(Token::new())))),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_two_timeout<M1, M2, A1, A2, T>(
+        &mut self,
+        _o: Timed<crate::OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action,
+    {
+        match self.offer_two_until(Some(Instant::now() + timeout))? {
+            Some(branch) => Ok(TimedBranch::Received(branch)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_n_timeout<C, T>(
+        &mut self,
+        _o: Timed<crate::OfferN<R2, C>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<C, T>, SessionError>
+    where
+        R1: Role,
+        R2: Role,
+        C: crate::Choice,
+        T: Action,
+    {
+        match self.offer_n_until(Some(Instant::now() + timeout))? {
+            Some(branch) => Ok(TimedBranch::Received(branch)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
         }
     }
 
-    fn close(self, _end: crate::End) {
-        drop(self);
+    fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        self.guard.start()
+    }
+
+    fn delegate(&mut self, _token: Token) {
+        self.guard.delegate();
+    }
+
+    fn close(mut self, _end: crate::End) -> Result<(), SessionError> {
+        self.guard.close();
+        Ok(())
+    }
+}
+
+/// A received segment has the sort it is classified as, see [segment_sort], and no label,
+/// unless it stands for an event of the retransmission timer.
+impl<R1, R2> RawChannel for NetChannel<'_, R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    fn receive_raw(
+        &mut self,
+        timeout: Option<Duration>,
+        labels: &[&str],
+    ) -> Result<Option<RawFrame>, SessionError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        Ok(match self.recv_segment(deadline, labels)? {
+            Received::Segment(packet) => Some(RawFrame {
+                label: None,
+                sort: self.sort(&packet),
+                packet,
+            }),
+            Received::Branch(label, packet) => Some(RawFrame {
+                label: Some(label),
+                sort: None,
+                packet,
+            }),
+            Received::TimedOut => None,
+        })
     }
 }
 
@@ -200,25 +293,387 @@ where
     pub fn new(
         rx: TcpTransportChannelIterator<'a>,
         tx: TransportSender,
+        local_addr: Ipv4Addr,
         remote_addr: Ipv4Addr,
     ) -> Self {
         NetChannel {
             rx,
             tx,
+            local_addr,
             remote_addr,
-            phantom: PhantomData::default(),
+            local_port: SERVER_PORT,
+            verify_checksum: true,
+            tcb: None,
+            retransmission: RetransmissionQueue::default(),
+            guard: SessionGuard::new::<Self>(),
+            phantom: PhantomData,
         }
     }
+
+    /// Receive the segments addressed to `port` instead of [SERVER_PORT],
+    /// e.g. the port of a client that opens the connection.
+    #[must_use]
+    pub fn with_local_port(mut self, port: u16) -> Self {
+        self.local_port = port;
+        self
+    }
+
+    /// Stop discarding received segments with an invalid checksum.
+    /// This is needed on the loopback interface, where the kernel offloads
+    /// checksum computation and segments are delivered with partial checksums.
+    #[must_use]
+    pub fn without_checksum_verification(mut self) -> Self {
+        self.verify_checksum = false;
+        self
+    }
+
+    /// Hand the [Tcb] of the connection to the channel, which processes the receive window
+    /// of every segment received from now on.
+    pub fn set_tcb(&mut self, tcb: Tcb) {
+        self.tcb = Some(tcb);
+    }
+
+    /// The [Tcb] of the connection, to build the segments to send from.
+    pub fn tcb(&mut self) -> Option<&mut Tcb> {
+        self.tcb.as_mut()
+    }
+
+    /// Give up on a segment after `threshold` retransmissions instead of
+    /// [crate::retransmission::RETRY_THRESHOLD].
+    #[must_use]
+    pub fn with_retry_threshold(mut self, threshold: u32) -> Self {
+        self.retransmission = RetransmissionQueue::new(threshold);
+        self
+    }
+
+    /// The segments sent and not acknowledged yet.
+    pub fn retransmission(&self) -> &RetransmissionQueue {
+        &self.retransmission
+    }
+
+    /// Send a segment to the remote address, and hold it for retransmission
+    /// if the channel has a [Tcb].
+    fn send_segment(&mut self, mut packet: Vec<u8>) -> Result<(), SessionError> {
+        if self.tcb.is_some() {
+            self.retransmission.push(&packet, Instant::now());
+        }
+        let length = packet.len();
+        let packet_inner =
+            MutableTcpPacket::new(&mut packet[..]).ok_or(DecodeError::Truncated { length })?;
+        self.tx
+            .send_to(packet_inner, IpAddr::V4(self.remote_addr))?;
+        Ok(())
+    }
+
+    /// Read the next segment addressed to us, or [Received::TimedOut] if `deadline` passes first.
+    /// Segments with an invalid checksum are discarded, as a receiving TCP would,
+    /// and so are segments outside of the receive window of the [Tcb].
+    /// The retransmission timer is served meanwhile, its events are taken as the branches
+    /// among `labels` that name them, see [NetChannel].
+    fn recv_segment(
+        &mut self,
+        deadline: Option<Instant>,
+        labels: &[&str],
+    ) -> Result<Received, SessionError> {
+        loop {
+            let until = match (deadline, self.retransmission.deadline()) {
+                (Some(deadline), Some(rto)) => Some(deadline.min(rto)),
+                (deadline, rto) => deadline.or(rto),
+            };
+            let next = match until {
+                Some(until) => {
+                    let remaining = until.saturating_duration_since(Instant::now());
+                    // a zero timeout would make the socket block forever
+                    let next = if remaining.is_zero() {
+                        None
+                    } else {
+                        self.rx.next_with_timeout(remaining)?
+                    };
+                    match next {
+                        Some(next) => next,
+                        None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                            return Ok(Received::TimedOut)
+                        }
+                        None => match self.expire(labels)? {
+                            Some(received) => return Ok(received),
+                            None => continue,
+                        },
+                    }
+                }
+                None => self.rx.next()?,
+            };
+            let (packet, source) = next;
+            // ignore packets that are not for us
+            if packet.get_destination() != self.local_port {
+                continue;
+            }
+            if self.verify_checksum {
+                let IpAddr::V4(source) = source else {
+                    continue;
+                };
+                if verify_checksum(&packet, &source, &self.local_addr).is_err() {
+                    continue;
+                }
+            }
+            let segment = packet.packet().to_vec();
+            if self.accept(&segment)? {
+                return Ok(Received::Segment(segment));
+            }
+        }
+    }
+
+    /// Serve the retransmission timer. Its events are returned as the branch among
+    /// `labels` that names them, or handled here if there is no such branch.
+    fn expire(&mut self, labels: &[&str]) -> Result<Option<Received>, SessionError> {
+        let Some(expiry) = self.retransmission.expire(Instant::now()) else {
+            return Ok(None);
+        };
+        let label = timer_label(&expiry, labels);
+        match expiry {
+            Expiry::Retransmit(segment) => match label {
+                Some(label) => Ok(Some(Received::Branch(label, segment))),
+                None => {
+                    self.send_segment(segment)?;
+                    Ok(None)
+                }
+            },
+            Expiry::ThresholdExceeded => {
+                let reset = match &mut self.tcb {
+                    Some(tcb) => tcb.segment(TcpFlags::RST, &[]),
+                    None => return Err(SessionError::RetryThresholdExceeded),
+                };
+                self.send_segment(reset.clone())?;
+                match label {
+                    Some(label) => Ok(Some(Received::Branch(label, reset))),
+                    None => Err(SessionError::RetryThresholdExceeded),
+                }
+            }
+        }
+    }
+
+    /// Process a received segment against the receive window of the [Tcb], if there is one.
+    /// An unacceptable segment is acknowledged unless it is a RST, and is not accepted.
+    fn accept(&mut self, segment: &[u8]) -> Result<bool, SessionError> {
+        let Some(tcb) = &mut self.tcb else {
+            return Ok(true);
+        };
+        let Some(packet) = TcpPacket::new(segment) else {
+            return Ok(false);
+        };
+        let acceptability = tcb.acceptability(&packet);
+        if acceptability == Acceptability::Acceptable {
+            tcb.receive(segment);
+            self.retransmission.acknowledge(tcb.snd_una, Instant::now());
+            return Ok(true);
+        }
+        if acceptability.needs_ack() {
+            let ack = tcb.segment(TcpFlags::ACK, &[]);
+            self.send_segment(ack)?;
+        }
+        Ok(false)
+    }
+
+    /// The payload sort of a received segment, see [segment_sort].
+    fn sort(&self, segment: &[u8]) -> Option<&'static str> {
+        let packet = TcpPacket::new(segment)?;
+        let acknowledges_fin = self
+            .tcb
+            .as_ref()
+            .is_some_and(|tcb| tcb.acknowledges_fin(&packet));
+        Some(segment_sort(packet.get_flags(), acknowledges_fin))
+    }
+
+    /// Whether a received segment is the message `M`, classified as one and of its sort.
+    fn is<M>(&self, segment: &[u8]) -> bool
+    where
+        M: Message,
+    {
+        M::matches(segment) && self.sort(segment) == Some(M::sort())
+    }
+
+    /// Receive the message of an [crate::OfferOne], or [None] if `deadline` passes first.
+    fn offer_one_until<M>(&mut self, deadline: Option<Instant>) -> Result<Option<M>, SessionError>
+    where
+        M: Message,
+    {
+        // as for a choice, segments that are not the offered message are rejected
+        while let Received::Segment(slice) = self.recv_segment(deadline, &[])? {
+            if self.is::<M>(&slice) {
+                return Ok(Some(M::from_net_representation(slice)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receive the branch of an [crate::OfferTwo], or [None] if `deadline` passes first.
+    fn offer_two_until<M1, M2, A1, A2>(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<OfferedTwo<M1, M2, A1, A2>>, SessionError>
+    where
+        M1: Message,
+        M2: Message,
+        A1: Action,
+        A2: Action,
+    {
+        // the branch is chosen by the flags of the segment,
+        // segments that are neither of the offered messages are rejected
+        while let Received::Segment(slice) = self.recv_segment(deadline, &[])? {
+            if self.is::<M1>(&slice) {
+                let message = M1::from_net_representation(slice)?;
+                return Ok(Some(Branch::Left((message, A1::new(Token::new())))));
+            }
+            if self.is::<M2>(&slice) {
+                let message = M2::from_net_representation(slice)?;
+                return Ok(Some(Branch::Right((message, A2::new(Token::new())))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receive the branch of an [crate::OfferN], or [None] if `deadline` passes first.
+    fn offer_n_until<C>(&mut self, deadline: Option<Instant>) -> Result<Option<C>, SessionError>
+    where
+        C: crate::Choice,
+    {
+        // the label is derived from the flags of the segment,
+        // segments that match none of the branches are rejected
+        loop {
+            let (label, slice) = match self.recv_segment(deadline, C::LABELS)? {
+                Received::Segment(slice) => {
+                    let label = self
+                        .sort(&slice)
+                        .and_then(|sort| C::label_of_sort(&slice, sort));
+                    match label {
+                        Some(label) => (label, slice),
+                        None => continue,
+                    }
+                }
+                Received::Branch(label, slice) => (label, slice),
+                Received::TimedOut => return Ok(None),
+            };
+            return Ok(Some(C::from_net_representation(
+                label,
+                slice,
+                Token::new(),
+            )?));
+        }
+    }
+}
+
+/// What ended the wait for a segment in [NetChannel::recv_segment].
+enum Received {
+    /// A segment addressed to us.
+    Segment(Vec<u8>),
+    /// An event of the retransmission timer, taken as the branch with the index and message.
+    Branch(usize, Vec<u8>),
+    /// The deadline passed first.
+    TimedOut,
+}
+
+/// The index of the branch among `labels` that names the event `expiry` of the retransmission timer,
+/// [RTO_EXCEEDED] for a retransmission and [RETRY_THRESHOLD_EXCEEDED] when giving up.
+fn timer_label(expiry: &Expiry, labels: &[&str]) -> Option<usize> {
+    let name = match expiry {
+        Expiry::Retransmit(_) => RTO_EXCEEDED,
+        Expiry::ThresholdExceeded => RETRY_THRESHOLD_EXCEEDED,
+    };
+    labels.iter().position(|label| *label == name)
+}
+
+/// Check the checksum of `segment` sent from `source` to `destination`.
+/// The checksum covers the IP pseudo-header, so it cannot be checked
+/// when decoding a message on its own and is checked by [NetChannel] instead.
+pub fn verify_checksum(
+    segment: &TcpPacket,
+    source: &Ipv4Addr,
+    destination: &Ipv4Addr,
+) -> Result<(), DecodeError> {
+    let expected = ipv4_checksum(segment, source, destination);
+    let found = segment.get_checksum();
+    if expected == found {
+        Ok(())
+    } else {
+        Err(DecodeError::Checksum { expected, found })
+    }
+}
+
+/// Check that `packet` is a well-formed TCP segment classified as `M`,
+/// checking its length, header length and control flags.
+pub fn validate<M>(packet: &[u8]) -> Result<(), DecodeError>
+where
+    M: Classify,
+{
+    let segment = TcpPacket::new(packet).ok_or(DecodeError::Truncated {
+        length: packet.len(),
+    })?;
+    let header_length = usize::from(segment.get_data_offset()) * 4;
+    if header_length < TcpPacket::minimum_packet_size() || header_length > packet.len() {
+        return Err(DecodeError::HeaderLength(segment.get_data_offset()));
+    }
+    if !M::classify(&segment) {
+        return Err(DecodeError::Flags(segment.get_flags()));
+    }
+    Ok(())
+}
+
+/// [Classify] is implemented by the message types of [NetChannel].
+/// On the wire a TCP segment carries no branch label, the branch of a choice is
+/// instead encoded in the header flags of the segment.
+/// [Classify] recognises the segments that carry a given message type,
+/// so that [NetChannel] can pick the branch of an offer from the received segment
+/// and reject segments that match no branch.
+/// Message types implementing [Classify] implement [Message::matches] with [classified].
+pub trait Classify {
+    fn classify(segment: &TcpPacket) -> bool;
+}
+
+/// The flags that tell the message types of the handshake and close apart.
+pub const CONTROL_FLAGS: u16 = TcpFlags::SYN | TcpFlags::ACK | TcpFlags::FIN | TcpFlags::RST;
+
+/// Whether `segment` has exactly `flags` set among [CONTROL_FLAGS].
+#[must_use]
+pub fn has_control_flags(segment: &TcpPacket, flags: u16) -> bool {
+    segment.get_flags() & CONTROL_FLAGS == flags
+}
+
+/// The payload sort in `tcp.ctx` of a segment with `flags`, given whether it acknowledges
+/// a FIN of its receiver. A FIN is a `SegFinAckSet` if it does, so the reply to a close,
+/// and a `SegFinSet` otherwise, so the start of a close, even though both carry FIN and ACK.
+/// This is the sort of the message types of [NetChannel] that take a segment,
+/// and the sort that [crate::pcap] checks a captured segment against.
+#[must_use]
+pub fn segment_sort(flags: u16, acknowledges_fin: bool) -> &'static str {
+    let has = |expected: u16| flags & expected == expected;
+    if has(TcpFlags::RST) {
+        "SegRstSet"
+    } else if has(TcpFlags::SYN | TcpFlags::ACK) {
+        "SegSynAckSet"
+    } else if has(TcpFlags::SYN) {
+        "SegSynSet"
+    } else if has(TcpFlags::FIN) && acknowledges_fin {
+        "SegFinAckSet"
+    } else if has(TcpFlags::FIN) {
+        "SegFinSet"
+    } else {
+        "SegAckSet"
+    }
+}
+
+/// Whether `packet` is a TCP segment that `M` classifies as its own.
+#[must_use]
+pub fn classified<M>(packet: &[u8]) -> bool
+where
+    M: Classify,
+{
+    TcpPacket::new(packet).is_some_and(|segment| M::classify(&segment))
 }
 
 /// [Syn] is the specific message type for a packet with
-/// the SYN flag set. We assume a well-behaved parser and
-/// leave the parsing implementation to the user. Hence,
-/// this demonstration has an extremely simplistic layout of
-/// message structs and does **no** error handling.
-/// Hence, it is possible to construct a [Syn] message out of
-/// a wrong packet. This should ideally be handled by checking
-/// that correct flags are set and returning errors.
+/// the SYN flag set and no other control flags.
+/// Decoding checks the header length and control flags of the segment,
+/// so decoding a [Syn] out of a wrong packet fails.
+/// The checksum is checked by [NetChannel] when the segment is received.
 pub struct Syn {
     pub packet: Vec<u8>,
 }
@@ -228,19 +683,31 @@ impl Message for Syn {
         self.packet
     }
 
-    fn from_net_representation(packet: Vec<u8>) -> Self {
-        Syn { packet }
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(Syn { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegSynSet"
+    }
+}
+
+impl Classify for Syn {
+    fn classify(segment: &TcpPacket) -> bool {
+        has_control_flags(segment, TcpFlags::SYN)
     }
 }
 
 /// [SynAck] is the specific message type for a packet with
-/// the SYN flag set. We assume a well-behaved parser and
-/// leave the parsing implementation to the user. Hence,
-/// this demonstration has an extremely simplistic layout of
-/// message structs and does **no** error handling.
-/// Hence, it is possible to construct a [SynAck] message out of
-/// a wrong packet. This should ideally be handled by checking
-/// that correct flags are set and returning errors.
+/// the SYN and ACK flags set and no other control flags.
+/// Decoding checks the header length and control flags of the segment,
+/// so decoding a [SynAck] out of a wrong packet fails.
+/// The checksum is checked by [NetChannel] when the segment is received.
 pub struct SynAck {
     pub packet: Vec<u8>,
 }
@@ -250,19 +717,31 @@ impl Message for SynAck {
         self.packet
     }
 
-    fn from_net_representation(packet: Vec<u8>) -> Self {
-        SynAck { packet }
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(SynAck { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegSynAckSet"
+    }
+}
+
+impl Classify for SynAck {
+    fn classify(segment: &TcpPacket) -> bool {
+        has_control_flags(segment, TcpFlags::SYN | TcpFlags::ACK)
     }
 }
 
 /// [Ack] is the specific message type for a packet with
-/// the SYN flag set. We assume a well-behaved parser and
-/// leave the parsing implementation to the user. Hence,
-/// this demonstration has an extremely simplistic layout of
-/// message structs and does **no** error handling.
-/// Hence, it is possible to construct a [Ack] message out of
-/// a wrong packet. This should ideally be handled by checking
-/// that correct flags are set and returning errors.
+/// the ACK flag set and no other control flags.
+/// Decoding checks the header length and control flags of the segment,
+/// so decoding a [Ack] out of a wrong packet fails.
+/// The checksum is checked by [NetChannel] when the segment is received.
 pub struct Ack {
     pub packet: Vec<u8>,
 }
@@ -272,19 +751,69 @@ impl Message for Ack {
         self.packet
     }
 
-    fn from_net_representation(packet: Vec<u8>) -> Self {
-        Ack { packet }
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(Ack { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegAckSet"
+    }
+}
+
+impl Classify for Ack {
+    fn classify(segment: &TcpPacket) -> bool {
+        has_control_flags(segment, TcpFlags::ACK)
+    }
+}
+
+/// [Fin] is the specific message type for a packet with
+/// the FIN and ACK flags set and no other control flags, that starts a close.
+/// In a synchronized connection every segment carries an ACK, so on the wire a [Fin]
+/// looks like a [FinAck]. [NetChannel] tells them apart by whether the segment
+/// acknowledges the FIN it sent, see [segment_sort].
+/// Decoding checks the header length and control flags of the segment,
+/// so decoding a [Fin] out of a wrong packet fails.
+/// The checksum is checked by [NetChannel] when the segment is received.
+pub struct Fin {
+    pub packet: Vec<u8>,
+}
+
+impl Message for Fin {
+    fn to_net_representation(self) -> Vec<u8> {
+        self.packet
+    }
+
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(Fin { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegFinSet"
+    }
+}
+
+impl Classify for Fin {
+    fn classify(segment: &TcpPacket) -> bool {
+        has_control_flags(segment, TcpFlags::FIN | TcpFlags::ACK)
     }
 }
 
 /// [FinAck] is the specific message type for a packet with
-/// the SYN flag set. We assume a well-behaved parser and
-/// leave the parsing implementation to the user. Hence,
-/// this demonstration has an extremely simplistic layout of
-/// message structs and does **no** error handling.
-/// Hence, it is possible to construct a [FinAck] message out of
-/// a wrong packet. This should ideally be handled by checking
-/// that correct flags are set and returning errors.
+/// the FIN and ACK flags set and no other control flags, that replies to a close
+/// by acknowledging the FIN of the peer, see [Fin].
+/// Decoding checks the header length and control flags of the segment,
+/// so decoding a [FinAck] out of a wrong packet fails.
+/// The checksum is checked by [NetChannel] when the segment is received.
 pub struct FinAck {
     pub packet: Vec<u8>,
 }
@@ -294,7 +823,161 @@ impl Message for FinAck {
         self.packet
     }
 
-    fn from_net_representation(packet: Vec<u8>) -> Self {
-        FinAck { packet }
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(FinAck { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegFinAckSet"
+    }
+}
+
+impl Classify for FinAck {
+    fn classify(segment: &TcpPacket) -> bool {
+        has_control_flags(segment, TcpFlags::FIN | TcpFlags::ACK)
+    }
+}
+
+/// [Rst] is the specific message type for a packet with
+/// the RST flag set. Any segment with the RST flag set is classified
+/// as a [Rst], regardless of the other control flags.
+/// Decoding checks the header length and control flags of the segment.
+pub struct Rst {
+    pub packet: Vec<u8>,
+}
+
+impl Message for Rst {
+    fn to_net_representation(self) -> Vec<u8> {
+        self.packet
+    }
+
+    fn from_net_representation(packet: Vec<u8>) -> Result<Self, DecodeError> {
+        validate::<Self>(&packet)?;
+        Ok(Rst { packet })
+    }
+
+    fn matches(packet: &[u8]) -> bool {
+        classified::<Self>(packet)
+    }
+
+    fn sort() -> &'static str {
+        "SegRstSet"
+    }
+}
+
+impl Classify for Rst {
+    fn classify(segment: &TcpPacket) -> bool {
+        segment.get_flags() & TcpFlags::RST != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        time::Instant,
+    };
+
+    use pnet::packet::tcp::{TcpFlags, TcpPacket};
+
+    use super::{segment_sort, timer_label, Ack, Classify, Fin, FinAck, Rst};
+    use crate::{
+        retransmission::{Expiry, RetransmissionQueue, INITIAL_RTO},
+        sequence::SeqNum,
+        tcb::Tcb,
+        Choice, End, Token,
+    };
+
+    crate::choice! {
+        enum DataTransfer {
+            Acceptable(Ack, End),
+            RtoExceeded(Ack, End),
+            RetryThresholdExceeded(Rst, End),
+        }
+    }
+
+    #[test]
+    fn timer_events_are_taken_as_their_branches() {
+        let mut tcb = Tcb::new(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156),
+            SeqNum::new(1000),
+        );
+        let mut queue = RetransmissionQueue::new(1);
+        let start = Instant::now();
+        let data = tcb.segment(TcpFlags::ACK, b"data");
+        queue.push(&data, start);
+
+        let expiry = queue
+            .expire(start + INITIAL_RTO)
+            .expect("the timer expired");
+        assert_eq!(expiry, Expiry::Retransmit(data.clone()));
+        let label = timer_label(&expiry, DataTransfer::LABELS).expect("an RtoExceeded branch");
+        let Ok(DataTransfer::RtoExceeded(ack, _)) =
+            DataTransfer::from_net_representation(label, data.clone(), Token::new())
+        else {
+            panic!("the retransmission is not the RtoExceeded branch");
+        };
+        assert_eq!(ack.packet, data);
+
+        let expiry = queue
+            .expire(start + INITIAL_RTO * 3)
+            .expect("the timer expired again");
+        assert_eq!(expiry, Expiry::ThresholdExceeded);
+        let label =
+            timer_label(&expiry, DataTransfer::LABELS).expect("a RetryThresholdExceeded branch");
+        let reset = tcb.segment(TcpFlags::RST, &[]);
+        assert!(matches!(
+            DataTransfer::from_net_representation(label, reset, Token::new()),
+            Ok(DataTransfer::RetryThresholdExceeded(..))
+        ));
+
+        // an offer without the branches handles the events itself
+        assert_eq!(timer_label(&expiry, &["Acceptable", "Fin"]), None);
+        assert_eq!(
+            timer_label(&Expiry::Retransmit(data), &["Acceptable", "Fin"]),
+            None
+        );
+    }
+
+    #[test]
+    fn a_fin_that_acknowledges_the_fin_sent_is_a_fin_ack() {
+        let server_addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+        let client_addr = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156);
+        let mut server = Tcb::new(server_addr, client_addr, SeqNum::new(1000));
+        let mut client = Tcb::new(client_addr, server_addr, SeqNum::new(5000));
+        server.receive(&client.segment(TcpFlags::SYN, &[]));
+        client.receive(&server.segment(TcpFlags::SYN | TcpFlags::ACK, &[]));
+        server.receive(&client.segment(TcpFlags::ACK, &[]));
+
+        // the client closes first, its FIN acknowledges no FIN of the server
+        let fin = client.segment(TcpFlags::FIN | TcpFlags::ACK, &[]);
+        let packet = TcpPacket::new(&fin).unwrap();
+        assert!(Fin::classify(&packet) && FinAck::classify(&packet));
+        assert!(!server.acknowledges_fin(&packet));
+        assert_eq!(
+            segment_sort(packet.get_flags(), server.acknowledges_fin(&packet)),
+            "SegFinSet"
+        );
+
+        // the reply of the server acknowledges it
+        server.receive(&fin);
+        let fin_ack = server.segment(TcpFlags::FIN | TcpFlags::ACK, &[]);
+        let packet = TcpPacket::new(&fin_ack).unwrap();
+        assert!(client.acknowledges_fin(&packet));
+        assert_eq!(
+            segment_sort(packet.get_flags(), client.acknowledges_fin(&packet)),
+            "SegFinAckSet"
+        );
+
+        // a FIN without an ACK is no message of a synchronized connection
+        let bare = client.segment(TcpFlags::FIN, &[]);
+        let packet = TcpPacket::new(&bare).unwrap();
+        assert!(!Fin::classify(&packet) && !FinAck::classify(&packet));
     }
 }
@@ -0,0 +1,751 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use pnet::packet::{
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    tcp::{TcpFlags, TcpPacket},
+};
+use tcp_st_model::{
+    ctx::Context,
+    export::{Automaton, Direction},
+    ProjectionError,
+};
+
+use crate::{monitor::Violation, net_channel::segment_sort, sequence::SeqNum};
+
+/// [PcapError] is returned when a file is not a valid pcap capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PcapError {
+    /// The file does not start with a pcap magic number, e.g. it is a pcapng file.
+    Magic(u32),
+    /// The file ends inside the header of the capture or of a record, at `offset`.
+    Truncated { offset: usize },
+    /// The capture is of a link type whose frames cannot be taken apart.
+    LinkType(u32),
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::Magic(magic) => write!(f, "not a pcap file, magic number {magic:#010x}"),
+            PcapError::Truncated { offset } => write!(f, "file is truncated at byte {offset}"),
+            PcapError::LinkType(link) => write!(f, "unsupported link type {link}"),
+        }
+    }
+}
+
+impl std::error::Error for PcapError {}
+
+/// [Record] is a frame of a capture, as many bytes of it as were captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: Duration,
+    pub data: Vec<u8>,
+}
+
+/// [Capture] is the content of a pcap file, as written by `tcpdump -w`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub link_type: u32,
+    pub records: Vec<Record>,
+}
+
+const LINK_NULL: u32 = 0;
+const LINK_ETHERNET: u32 = 1;
+const LINK_RAW: u32 = 101;
+const LINK_LINUX_SLL: u32 = 113;
+const LINK_LINUX_SLL2: u32 = 276;
+
+impl Capture {
+    /// Read a capture in either byte order, with microsecond or nanosecond timestamps.
+    pub fn parse(bytes: &[u8]) -> Result<Capture, PcapError> {
+        let header = bytes.get(..24).ok_or(PcapError::Truncated {
+            offset: bytes.len(),
+        })?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (big_endian, nanoseconds) = match magic {
+            0xa1b2_c3d4 => (false, false),
+            0xa1b2_3c4d => (false, true),
+            0xd4c3_b2a1 => (true, false),
+            0x4d3c_b2a1 => (true, true),
+            _ => return Err(PcapError::Magic(magic)),
+        };
+        let word = |bytes: &[u8], at: usize| {
+            let word = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+            if big_endian {
+                u32::from_be_bytes(word)
+            } else {
+                u32::from_le_bytes(word)
+            }
+        };
+        let link_type = word(header, 20) & 0x0fff_ffff;
+        let mut records = Vec::new();
+        let mut offset = 24;
+        while offset < bytes.len() {
+            let header = bytes.get(offset..offset + 16).ok_or(PcapError::Truncated {
+                offset: bytes.len(),
+            })?;
+            let fraction = u64::from(word(header, 4));
+            let timestamp = Duration::from_secs(u64::from(word(header, 0)))
+                + if nanoseconds {
+                    Duration::from_nanos(fraction)
+                } else {
+                    Duration::from_micros(fraction)
+                };
+            let length = word(header, 8) as usize;
+            let data =
+                bytes
+                    .get(offset + 16..offset + 16 + length)
+                    .ok_or(PcapError::Truncated {
+                        offset: bytes.len(),
+                    })?;
+            records.push(Record {
+                timestamp,
+                data: data.to_vec(),
+            });
+            offset += 16 + length;
+        }
+        Ok(Capture { link_type, records })
+    }
+
+    /// The TCP segments of the capture, in capture order.
+    /// Frames that do not carry a TCP segment over IPv4 or IPv6 are skipped,
+    /// as are IPv4 fragments other than the first.
+    pub fn segments(&self) -> Result<Vec<Segment>, PcapError> {
+        let mut segments = Vec::new();
+        for (index, record) in self.records.iter().enumerate() {
+            let Some(ip) = self.network_layer(&record.data)? else {
+                continue;
+            };
+            if let Some(segment) = Segment::parse(index + 1, record.timestamp, ip) {
+                segments.push(segment);
+            }
+        }
+        Ok(segments)
+    }
+
+    /// The IP packet in `frame`, if the frame carries one.
+    fn network_layer<'a>(&self, frame: &'a [u8]) -> Result<Option<&'a [u8]>, PcapError> {
+        let (offset, ethertype) = match self.link_type {
+            LINK_RAW => return Ok(Some(frame)),
+            LINK_NULL => return Ok(frame.get(4..)),
+            LINK_ETHERNET => {
+                let mut offset = 12;
+                // Skip 802.1Q and 802.1ad tags.
+                while matches!(ethertype(frame, offset), Some(0x8100 | 0x88a8)) {
+                    offset += 4;
+                }
+                (offset + 2, ethertype(frame, offset))
+            }
+            LINK_LINUX_SLL => (16, ethertype(frame, 14)),
+            LINK_LINUX_SLL2 => (20, ethertype(frame, 0)),
+            link => return Err(PcapError::LinkType(link)),
+        };
+        Ok(match ethertype {
+            Some(0x0800 | 0x86dd) => frame.get(offset..),
+            _ => None,
+        })
+    }
+}
+
+fn ethertype(frame: &[u8], offset: usize) -> Option<u16> {
+    frame
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// [Segment] is the header of a captured TCP segment.
+/// The length of the payload is taken from the IP header, so it is known
+/// even if the capture cut the payload off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// The number of the frame in the capture, starting at 1 as in tcpdump and Wireshark.
+    pub frame: usize,
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence: SeqNum,
+    pub acknowledgement: SeqNum,
+    pub flags: u16,
+    pub payload: usize,
+}
+
+impl Segment {
+    fn parse(frame: usize, timestamp: Duration, ip: &[u8]) -> Option<Segment> {
+        let (source, destination, tcp, length) = match ip.first()? >> 4 {
+            4 => {
+                let packet = Ipv4Packet::new(ip)?;
+                let header = usize::from(packet.get_header_length()) * 4;
+                if packet.get_next_level_protocol() != IpNextHeaderProtocols::Tcp
+                    || packet.get_fragment_offset() != 0
+                {
+                    return None;
+                }
+                let length = usize::from(packet.get_total_length()).checked_sub(header)?;
+                (
+                    IpAddr::V4(packet.get_source()),
+                    IpAddr::V4(packet.get_destination()),
+                    ip.get(header..)?,
+                    length,
+                )
+            }
+            6 => {
+                let packet = Ipv6Packet::new(ip)?;
+                if packet.get_next_header() != IpNextHeaderProtocols::Tcp {
+                    return None;
+                }
+                (
+                    IpAddr::V6(packet.get_source()),
+                    IpAddr::V6(packet.get_destination()),
+                    ip.get(Ipv6Packet::minimum_packet_size()..)?,
+                    usize::from(packet.get_payload_length()),
+                )
+            }
+            _ => return None,
+        };
+        let segment = TcpPacket::new(tcp)?;
+        let header = usize::from(segment.get_data_offset()) * 4;
+        Some(Segment {
+            frame,
+            timestamp,
+            source: SocketAddr::new(source, segment.get_source()),
+            destination: SocketAddr::new(destination, segment.get_destination()),
+            sequence: segment.get_sequence().into(),
+            acknowledgement: segment.get_acknowledgement().into(),
+            flags: segment.get_flags(),
+            payload: length.saturating_sub(header),
+        })
+    }
+
+    fn has(&self, flags: u16) -> bool {
+        self.flags & flags == flags
+    }
+
+    /// The length of the segment in sequence space, counting SYN and FIN.
+    fn sequence_length(&self) -> u32 {
+        self.payload as u32
+            + u32::from(self.has(TcpFlags::SYN))
+            + u32::from(self.has(TcpFlags::FIN))
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (TcpFlags::SYN, 'S'),
+            (TcpFlags::FIN, 'F'),
+            (TcpFlags::PSH, 'P'),
+            (TcpFlags::RST, 'R'),
+            (TcpFlags::ACK, '.'),
+        ];
+        let flags: String = names
+            .iter()
+            .filter(|(flag, _)| self.has(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+        write!(
+            f,
+            "frame {}: {} > {} [{flags}] seq {} ack {} length {}",
+            self.frame,
+            self.source,
+            self.destination,
+            self.sequence,
+            self.acknowledgement,
+            self.payload
+        )
+    }
+}
+
+/// [Connection] is the segments exchanged between two endpoints, from the SYN that opened it.
+/// A connection whose opening is not in the capture has no client and server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    /// The endpoint that sent the first SYN and its peer, if the capture saw the opening.
+    pub endpoints: Option<(SocketAddr, SocketAddr)>,
+    pub segments: Vec<Segment>,
+}
+
+/// Group `segments` by connection, in the order the connections were first seen.
+/// A SYN that is not a retransmission starts a new connection between the same endpoints.
+pub fn connections(segments: Vec<Segment>) -> Vec<Connection> {
+    let mut connections: Vec<Connection> = Vec::new();
+    let mut current = HashMap::new();
+    for segment in segments {
+        let key = if segment.source <= segment.destination {
+            (segment.source, segment.destination)
+        } else {
+            (segment.destination, segment.source)
+        };
+        let opening = segment.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN;
+        let index = match current.get(&key) {
+            Some(&index) => {
+                let connection: &Connection = &connections[index];
+                let retransmitted = connection.segments.iter().all(|s| {
+                    s.source == segment.source
+                        && s.sequence == segment.sequence
+                        && s.has(TcpFlags::SYN)
+                });
+                if opening && !retransmitted {
+                    None
+                } else {
+                    Some(index)
+                }
+            }
+            None => None,
+        };
+        let index = index.unwrap_or_else(|| {
+            connections.push(Connection {
+                endpoints: opening.then_some((segment.source, segment.destination)),
+                segments: Vec::new(),
+            });
+            current.insert(key, connections.len() - 1);
+            connections.len() - 1
+        });
+        connections[index].segments.push(segment);
+    }
+    connections
+}
+
+/// [Classified] is a segment as a message of the model: its payload sort and,
+/// where the header of the segment tells it, its label.
+/// Segments that only carry an acknowledgement and data cannot be told apart on the wire
+/// by the labels of `tcp.ctx` (`acceptable`, `rto_exceeded`, `retransmit`), so they have no label
+/// unless they acknowledge a FIN of the peer, which makes them an `ack`.
+/// A RST has no label either, in `tcp.ctx` it ends the retransmission loop as `retry_threshold_exceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Classified {
+    pub label: Option<&'static str>,
+    pub sort: &'static str,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.label.unwrap_or("_"), self.sort)
+    }
+}
+
+/// [Classifier] classifies the segments of a connection in order,
+/// remembering the FIN of each side to tell `fin` from `fin_ack` and `ack`.
+/// The sort is the one [crate::net_channel::NetChannel] receives the segment as,
+/// see [segment_sort], and the label follows from it.
+#[derive(Debug, Default)]
+struct Classifier {
+    fins: HashMap<SocketAddr, SeqNum>,
+}
+
+impl Classifier {
+    fn classify(&mut self, segment: &Segment) -> Classified {
+        let acknowledges_fin = segment.has(TcpFlags::ACK)
+            && self.fins.get(&segment.destination).copied() == Some(segment.acknowledgement);
+        if segment.has(TcpFlags::FIN) {
+            self.fins
+                .insert(segment.source, segment.sequence + segment.sequence_length());
+        }
+        let sort = segment_sort(segment.flags, acknowledges_fin);
+        let label = match sort {
+            "SegSynSet" => Some("syn"),
+            "SegSynAckSet" => Some("syn_ack"),
+            "SegFinSet" => Some("fin"),
+            "SegFinAckSet" => Some("fin_ack"),
+            "SegAckSet" if acknowledges_fin && segment.payload == 0 => Some("ack"),
+            _ => None,
+        };
+        Classified { label, sort }
+    }
+}
+
+/// [Outcome] of replaying a connection through the automata of the client and the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Both roles reached the end of their local types.
+    Conforms,
+    /// Every segment was allowed, but the capture stops before both roles could end,
+    /// which are in the given states.
+    Incomplete {
+        client: Vec<usize>,
+        server: Vec<usize>,
+    },
+    /// The segment in frame `frame` is not allowed by the local type of one of the roles.
+    Violated { frame: usize, violation: Violation },
+    /// The capture does not contain the opening SYN of the connection, so the roles are unknown.
+    Unopened,
+}
+
+/// [Conformance] is the outcome of a connection, with the classification of its segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conformance {
+    pub connection: Connection,
+    pub classified: Vec<Classified>,
+    pub outcome: Outcome,
+}
+
+/// [Replay] follows the automaton of one role as a nondeterministic one.
+/// Only the communication with `peer` is on the wire, so the transitions
+/// to other roles, e.g. to the user of the role, are taken silently.
+struct Replay {
+    automaton: Automaton,
+    peer: String,
+    states: BTreeSet<usize>,
+}
+
+impl Replay {
+    fn new(automaton: Automaton, peer: &str) -> Replay {
+        let mut replay = Replay {
+            automaton,
+            peer: peer.to_string(),
+            states: BTreeSet::new(),
+        };
+        replay.states = replay.closure(BTreeSet::from([0]));
+        replay
+    }
+
+    fn closure(&self, mut states: BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut pending: Vec<usize> = states.iter().copied().collect();
+        while let Some(state) = pending.pop() {
+            for transition in &self.automaton.transitions {
+                if transition.source == state
+                    && transition.peer != self.peer
+                    && states.insert(transition.target)
+                {
+                    pending.push(transition.target);
+                }
+            }
+        }
+        states
+    }
+
+    fn step(&mut self, direction: Direction, message: Classified) -> Result<(), Violation> {
+        let next: BTreeSet<usize> = self
+            .automaton
+            .transitions
+            .iter()
+            .filter(|t| {
+                self.states.contains(&t.source)
+                    && t.direction == direction
+                    && t.peer == self.peer
+                    && message.label.is_none_or(|label| label == t.label)
+                    && message.sort == t.sort
+            })
+            .map(|t| t.target)
+            .collect();
+        if next.is_empty() {
+            let symbol = match direction {
+                Direction::Send => '!',
+                Direction::Receive => '?',
+            };
+            let expected = self
+                .automaton
+                .transitions
+                .iter()
+                .filter(|t| self.states.contains(&t.source) && t.peer == self.peer)
+                .map(|t| t.to_string())
+                .collect::<BTreeSet<_>>();
+            return Err(Violation {
+                role: self.automaton.role.clone(),
+                states: self.states.iter().copied().collect(),
+                action: format!("{}{symbol}{message}", self.peer),
+                expected: expected.into_iter().collect(),
+            });
+        }
+        self.states = self.closure(next);
+        Ok(())
+    }
+
+    fn ended(&self) -> bool {
+        self.states
+            .iter()
+            .any(|state| self.automaton.finals.contains(state))
+    }
+}
+
+/// Replay every connection of `segments` through the local types of `client` and `server` in `context`,
+/// e.g. `client_system` and `server_system` of `tcp.ctx`.
+/// Both roles take part in every segment: the sender sends it and the receiver receives it,
+/// in the order of the capture.
+pub fn conformance(
+    context: &Context,
+    client: &str,
+    server: &str,
+    segments: Vec<Segment>,
+) -> Result<Vec<Conformance>, ProjectionError> {
+    let automaton = |role: &str| {
+        context
+            .entry(role)
+            .map(|entry| Automaton::new(role, &entry.local.to_local()))
+            .ok_or_else(|| ProjectionError {
+                role: role.to_string(),
+                message: "the context has no entry for the role".to_string(),
+            })
+    };
+    let (client_automaton, server_automaton) = (automaton(client)?, automaton(server)?);
+    Ok(connections(segments)
+        .into_iter()
+        .map(|connection| {
+            let mut classifier = Classifier::default();
+            let classified: Vec<Classified> = connection
+                .segments
+                .iter()
+                .map(|segment| classifier.classify(segment))
+                .collect();
+            let outcome = match connection.endpoints {
+                None => Outcome::Unopened,
+                Some((client_address, _)) => replay(
+                    Replay::new(client_automaton.clone(), server),
+                    Replay::new(server_automaton.clone(), client),
+                    client_address,
+                    &connection.segments,
+                    &classified,
+                ),
+            };
+            Conformance {
+                connection,
+                classified,
+                outcome,
+            }
+        })
+        .collect())
+}
+
+fn replay(
+    mut client: Replay,
+    mut server: Replay,
+    client_address: SocketAddr,
+    segments: &[Segment],
+    classified: &[Classified],
+) -> Outcome {
+    for (segment, message) in segments.iter().zip(classified) {
+        let (sender, receiver) = if segment.source == client_address {
+            (&mut client, &mut server)
+        } else {
+            (&mut server, &mut client)
+        };
+        let stepped = sender
+            .step(Direction::Send, *message)
+            .and_then(|()| receiver.step(Direction::Receive, *message));
+        if let Err(violation) = stepped {
+            return Outcome::Violated {
+                frame: segment.frame,
+                violation,
+            };
+        }
+    }
+    if client.ended() && server.ended() {
+        Outcome::Conforms
+    } else {
+        Outcome::Incomplete {
+            client: client.states.into_iter().collect(),
+            server: server.states.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+    use pnet::packet::tcp::TcpFlags;
+    use tcp_st_model::ctx::Context;
+
+    use super::{conformance, connections, Capture, Classified, Outcome, PcapError, LINK_RAW};
+    use crate::{sequence::SeqNum, tcb::Tcb};
+
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156);
+
+    /// The next segment of `from` with `flags`, received by `to`, in an IPv4 packet.
+    fn exchange(from: &mut Tcb, to: &mut Tcb, flags: u16) -> Vec<u8> {
+        let tcp = from.segment(flags, &[]);
+        to.receive(&tcp);
+        let length = u16::try_from(20 + tcp.len()).unwrap();
+        let mut ip = vec![0x45, 0];
+        ip.extend(length.to_be_bytes());
+        ip.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+        ip.extend(from.local.ip().octets());
+        ip.extend(from.remote.ip().octets());
+        ip.extend(tcp);
+        ip
+    }
+
+    /// A pcap file of raw IP `frames`, one per millisecond, in little or big endian.
+    fn pcap(frames: &[Vec<u8>], big_endian: bool) -> Vec<u8> {
+        let word = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut bytes = Vec::new();
+        bytes.extend(word(0xa1b2_c3d4));
+        bytes.extend(if big_endian {
+            [0, 2, 0, 4]
+        } else {
+            [2, 0, 4, 0]
+        });
+        bytes.extend(word(0));
+        bytes.extend(word(0));
+        bytes.extend(word(65535));
+        bytes.extend(word(LINK_RAW));
+        for (i, frame) in frames.iter().enumerate() {
+            let length = u32::try_from(frame.len()).unwrap();
+            bytes.extend(word(0));
+            bytes.extend(word(u32::try_from(i).unwrap() * 1000));
+            bytes.extend(word(length));
+            bytes.extend(word(length));
+            bytes.extend(frame);
+        }
+        bytes
+    }
+
+    /// The blocks of a client and a server, and the frames of the handshake between them.
+    fn handshake() -> (Tcb, Tcb, Vec<Vec<u8>>) {
+        let mut client = Tcb::new(CLIENT, SERVER, SeqNum::new(5000));
+        let mut server = Tcb::new(SERVER, CLIENT, SeqNum::new(1000));
+        let frames = vec![
+            exchange(&mut client, &mut server, TcpFlags::SYN),
+            exchange(&mut server, &mut client, TcpFlags::SYN | TcpFlags::ACK),
+            exchange(&mut client, &mut server, TcpFlags::ACK),
+        ];
+        (client, server, frames)
+    }
+
+    fn check(frames: &[Vec<u8>]) -> (Vec<Classified>, Outcome) {
+        let context = Context::parse(include_str!("../../tcp.ctx")).unwrap();
+        let segments = Capture::parse(&pcap(frames, false))
+            .unwrap()
+            .segments()
+            .unwrap();
+        let mut checked =
+            conformance(&context, "client_system", "server_system", segments).unwrap();
+        assert_eq!(checked.len(), 1);
+        let checked = checked.remove(0);
+        (checked.classified, checked.outcome)
+    }
+
+    fn labels(classified: &[Classified]) -> Vec<String> {
+        classified.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn captures_are_read_in_either_byte_order() {
+        let (_, _, frames) = handshake();
+        let little = Capture::parse(&pcap(&frames, false)).unwrap();
+        let big = Capture::parse(&pcap(&frames, true)).unwrap();
+        assert_eq!(little, big);
+        assert_eq!(little.link_type, LINK_RAW);
+        assert_eq!(little.records.len(), 3);
+        assert_eq!(little.records[2].data, frames[2]);
+
+        let segments = little.segments().unwrap();
+        assert_eq!(segments[1].frame, 2);
+        assert_eq!(segments[1].source, SocketAddr::V4(SERVER));
+        assert_eq!(segments[1].sequence, SeqNum::new(1000));
+        assert_eq!(segments[1].acknowledgement, SeqNum::new(5001));
+        assert_eq!(segments[1].flags, TcpFlags::SYN | TcpFlags::ACK);
+
+        let bytes = pcap(&frames, false);
+        assert_eq!(
+            Capture::parse(&bytes[..bytes.len() - 1]),
+            Err(PcapError::Truncated {
+                offset: bytes.len() - 1
+            })
+        );
+        // the magic number of pcapng
+        assert_eq!(
+            Capture::parse(&[0x0a, 0x0d, 0x0d, 0x0a].repeat(6)),
+            Err(PcapError::Magic(0x0a0d_0d0a))
+        );
+    }
+
+    #[test]
+    fn a_new_syn_starts_a_new_connection_and_a_retransmitted_one_does_not() {
+        let (_, _, mut frames) = handshake();
+        frames.insert(1, frames[0].clone());
+        let (_, _, again) = handshake();
+        frames.extend(again);
+        let segments = Capture::parse(&pcap(&frames, false))
+            .unwrap()
+            .segments()
+            .unwrap();
+        let connections = connections(segments);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].segments.len(), 4);
+        assert_eq!(
+            connections[1].endpoints,
+            Some((SocketAddr::V4(CLIENT), SocketAddr::V4(SERVER)))
+        );
+    }
+
+    #[test]
+    fn a_handshake_and_close_conforms() {
+        let (mut client, mut server, mut frames) = handshake();
+        // the client closes, the server replies with its own FIN that acknowledges it
+        frames.push(exchange(
+            &mut client,
+            &mut server,
+            TcpFlags::FIN | TcpFlags::ACK,
+        ));
+        frames.push(exchange(
+            &mut server,
+            &mut client,
+            TcpFlags::FIN | TcpFlags::ACK,
+        ));
+        frames.push(exchange(&mut client, &mut server, TcpFlags::ACK));
+        let (classified, outcome) = check(&frames);
+        assert_eq!(
+            labels(&classified),
+            [
+                "syn(SegSynSet)",
+                "syn_ack(SegSynAckSet)",
+                "_(SegAckSet)",
+                "fin(SegFinSet)",
+                "fin_ack(SegFinAckSet)",
+                "ack(SegAckSet)"
+            ]
+        );
+        assert_eq!(outcome, Outcome::Conforms);
+    }
+
+    #[test]
+    fn a_fin_before_the_handshake_is_complete_violates_at_its_frame() {
+        let (mut client, mut server, mut frames) = handshake();
+        frames.truncate(2);
+        frames.push(exchange(
+            &mut client,
+            &mut server,
+            TcpFlags::FIN | TcpFlags::ACK,
+        ));
+        frames.push(exchange(
+            &mut server,
+            &mut client,
+            TcpFlags::FIN | TcpFlags::ACK,
+        ));
+        let (classified, outcome) = check(&frames);
+        assert_eq!(classified[2].to_string(), "fin(SegFinSet)");
+        let Outcome::Violated { frame, violation } = outcome else {
+            panic!("the capture conforms: {outcome:?}");
+        };
+        assert_eq!(frame, 3);
+        assert_eq!(violation.role, "client_system");
+        assert_eq!(violation.action, "server_system!fin(SegFinSet)");
+    }
+}
@@ -0,0 +1,400 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use pnet::packet::tcp::TcpPacket;
+
+use crate::{sequence::SeqNum, tcb::length};
+
+/// The retransmission timeout before the first round-trip time is measured, RFC 6298 section 2.1.
+pub const INITIAL_RTO: Duration = Duration::from_secs(1);
+
+/// The lower bound of the retransmission timeout, RFC 6298 section 2.4.
+pub const MIN_RTO: Duration = Duration::from_secs(1);
+
+/// The upper bound of the retransmission timeout, RFC 6298 section 2.5.
+pub const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// The granularity G of the clock the round-trip time is measured with.
+pub const GRANULARITY: Duration = Duration::from_millis(1);
+
+/// How many times a segment is retransmitted before the connection gives up,
+/// R2 of RFC 9293 section 3.8.3. Backing off from [INITIAL_RTO] this waits for
+/// over the 100 seconds the RFC recommends.
+pub const RETRY_THRESHOLD: u32 = 7;
+
+/// The label of the branch a [crate::net_channel::NetChannel] takes when the
+/// retransmission timer expires, `rto_exceeded` in `tcp.ctx`.
+pub const RTO_EXCEEDED: &str = "RtoExceeded";
+
+/// The label of the branch a [crate::net_channel::NetChannel] takes when a segment
+/// was retransmitted [RETRY_THRESHOLD] times, `retry_threshold_exceeded` in `tcp.ctx`.
+pub const RETRY_THRESHOLD_EXCEEDED: &str = "RetryThresholdExceeded";
+
+/// [RtoEstimator] computes the retransmission timeout of RFC 6298 from the
+/// smoothed round-trip time SRTT and its variation RTTVAR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtoEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl RtoEstimator {
+    /// An estimator that has not measured a round-trip time yet and waits [INITIAL_RTO].
+    #[must_use]
+    pub fn new() -> Self {
+        RtoEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+
+    /// SRTT, or [None] before the first measurement.
+    #[must_use]
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// RTTVAR.
+    #[must_use]
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// The current retransmission timeout.
+    #[must_use]
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    /// Account for a measured round-trip time as in RFC 6298 sections 2.2 and 2.3,
+    /// with alpha = 1/8 and beta = 1/4. This also undoes any back off.
+    pub fn sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 3 / 4 + srtt.abs_diff(rtt) / 4;
+                srtt * 7 / 8 + rtt / 8
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + GRANULARITY.max(self.rttvar * 4)).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Double the timeout after the timer expired, RFC 6298 section 5.5.
+    pub fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+}
+
+impl Default for RtoEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [Expiry] is what a [RetransmissionQueue] asks for when its timer expires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expiry {
+    /// Retransmit the earliest unacknowledged segment.
+    Retransmit(Vec<u8>),
+    /// The segment was retransmitted as often as allowed, the connection is to be aborted.
+    ThresholdExceeded,
+}
+
+/// A segment that was sent and is not acknowledged yet.
+#[derive(Debug, Clone)]
+struct Unacknowledged {
+    segment: Vec<u8>,
+    /// The sequence number after the segment.
+    end: SeqNum,
+    sent: Instant,
+    retransmitted: bool,
+}
+
+/// [RetransmissionQueue] holds the segments sent and not acknowledged yet, and runs the
+/// retransmission timer of RFC 6298 section 5 over them. Round-trip times are only measured
+/// on segments that were not retransmitted, Karn's algorithm, and the timeout backs off
+/// exponentially each time the timer expires.
+#[derive(Debug, Clone)]
+pub struct RetransmissionQueue {
+    segments: VecDeque<Unacknowledged>,
+    estimator: RtoEstimator,
+    deadline: Option<Instant>,
+    retries: u32,
+    threshold: u32,
+}
+
+impl RetransmissionQueue {
+    /// An empty queue that gives up after `threshold` retransmissions of a segment.
+    #[must_use]
+    pub fn new(threshold: u32) -> Self {
+        RetransmissionQueue {
+            segments: VecDeque::new(),
+            estimator: RtoEstimator::new(),
+            deadline: None,
+            retries: 0,
+            threshold,
+        }
+    }
+
+    /// The estimator of the retransmission timeout.
+    #[must_use]
+    pub fn estimator(&self) -> &RtoEstimator {
+        &self.estimator
+    }
+
+    /// When the timer expires, or [None] if it is not running.
+    #[must_use]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// How many times the earliest unacknowledged segment was retransmitted.
+    #[must_use]
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// The number of unacknowledged segments.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Whether every segment sent was acknowledged.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Queue a segment sent at `now`, and start the timer if it is not running.
+    /// Segments that take no sequence space are not acknowledged and are not queued,
+    /// and neither are segments that do not go past the ones queued, i.e. retransmissions.
+    pub fn push(&mut self, segment: &[u8], now: Instant) {
+        let Some(packet) = TcpPacket::new(segment) else {
+            return;
+        };
+        let length = length(&packet);
+        let end = SeqNum::from(packet.get_sequence()) + length;
+        if length == 0 || self.segments.back().is_some_and(|last| end.le(last.end)) {
+            return;
+        }
+        self.segments.push_back(Unacknowledged {
+            segment: segment.to_vec(),
+            end,
+            sent: now,
+            retransmitted: false,
+        });
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.estimator.rto());
+        }
+    }
+
+    /// Drop the segments that SND.UNA `snd_una` acknowledges completely. The latest of them
+    /// gives a round-trip time sample unless it was retransmitted. The timer is restarted
+    /// if segments remain and stopped otherwise, RFC 6298 sections 5.2 and 5.3.
+    pub fn acknowledge(&mut self, snd_una: SeqNum, now: Instant) {
+        let mut acknowledged = None;
+        while self
+            .segments
+            .front()
+            .is_some_and(|front| front.end.le(snd_una))
+        {
+            acknowledged = self.segments.pop_front();
+        }
+        let Some(acknowledged) = acknowledged else {
+            return;
+        };
+        if !acknowledged.retransmitted {
+            self.estimator
+                .sample(now.saturating_duration_since(acknowledged.sent));
+        }
+        self.retries = 0;
+        self.deadline = if self.segments.is_empty() {
+            None
+        } else {
+            Some(now + self.estimator.rto())
+        };
+    }
+
+    /// Check the timer at `now`. If it expired, the earliest segment is to be retransmitted,
+    /// the timeout backs off and the timer restarts, RFC 6298 sections 5.4 to 5.6,
+    /// unless the segment was retransmitted `threshold` times already.
+    pub fn expire(&mut self, now: Instant) -> Option<Expiry> {
+        if self.deadline.is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        let Some(front) = self.segments.front_mut() else {
+            self.deadline = None;
+            return None;
+        };
+        if self.retries >= self.threshold {
+            self.deadline = None;
+            return Some(Expiry::ThresholdExceeded);
+        }
+        self.retries += 1;
+        front.retransmitted = true;
+        self.estimator.backoff();
+        self.deadline = Some(now + self.estimator.rto());
+        Some(Expiry::Retransmit(front.segment.clone()))
+    }
+}
+
+impl Default for RetransmissionQueue {
+    fn default() -> Self {
+        Self::new(RETRY_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        time::{Duration, Instant},
+    };
+
+    use pnet::packet::tcp::TcpFlags;
+
+    use super::{Expiry, RetransmissionQueue, RtoEstimator, INITIAL_RTO, MAX_RTO, MIN_RTO};
+    use crate::{sequence::SeqNum, tcb::Tcb};
+
+    fn tcb() -> Tcb {
+        Tcb::new(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156),
+            SeqNum::new(1000),
+        )
+    }
+
+    #[test]
+    fn first_sample_initializes_srtt_and_rttvar() {
+        let mut estimator = RtoEstimator::new();
+        assert_eq!(estimator.srtt(), None);
+        assert_eq!(estimator.rto(), INITIAL_RTO);
+        estimator.sample(Duration::from_secs(2));
+        assert_eq!(estimator.srtt(), Some(Duration::from_secs(2)));
+        assert_eq!(estimator.rttvar(), Duration::from_secs(1));
+        // SRTT + 4 * RTTVAR
+        assert_eq!(estimator.rto(), Duration::from_secs(6));
+    }
+
+    #[test]
+    fn later_samples_update_srtt_and_rttvar() {
+        let mut estimator = RtoEstimator::new();
+        estimator.sample(Duration::from_secs(2));
+        estimator.sample(Duration::from_secs(1));
+        // RTTVAR = 3/4 * 1 s + 1/4 * |2 s - 1 s|, SRTT = 7/8 * 2 s + 1/8 * 1 s
+        assert_eq!(estimator.rttvar(), Duration::from_secs(1));
+        assert_eq!(estimator.srtt(), Some(Duration::from_millis(1875)));
+        assert_eq!(estimator.rto(), Duration::from_millis(5875));
+    }
+
+    #[test]
+    fn timeout_is_at_least_one_second() {
+        let mut estimator = RtoEstimator::new();
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(estimator.srtt(), Some(Duration::from_millis(100)));
+        assert_eq!(estimator.rto(), MIN_RTO);
+    }
+
+    #[test]
+    fn timeout_backs_off_up_to_the_maximum() {
+        let mut estimator = RtoEstimator::new();
+        estimator.backoff();
+        assert_eq!(estimator.rto(), INITIAL_RTO * 2);
+        estimator.backoff();
+        assert_eq!(estimator.rto(), INITIAL_RTO * 4);
+        for _ in 0..10 {
+            estimator.backoff();
+        }
+        assert_eq!(estimator.rto(), MAX_RTO);
+        // a new measurement undoes the back off
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(estimator.rto(), MIN_RTO);
+    }
+
+    #[test]
+    fn acknowledged_segment_gives_a_sample() {
+        let mut tcb = tcb();
+        let mut queue = RetransmissionQueue::default();
+        let start = Instant::now();
+        queue.push(&tcb.segment(TcpFlags::SYN, &[]), start);
+        assert_eq!(queue.deadline(), Some(start + INITIAL_RTO));
+        queue.acknowledge(tcb.snd_nxt, start + Duration::from_millis(300));
+        assert!(queue.is_empty());
+        assert_eq!(queue.deadline(), None);
+        assert_eq!(queue.estimator().srtt(), Some(Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn retransmitted_segment_gives_no_sample() {
+        let mut tcb = tcb();
+        let mut queue = RetransmissionQueue::default();
+        let start = Instant::now();
+        let syn = tcb.segment(TcpFlags::SYN, &[]);
+        queue.push(&syn, start);
+        assert_eq!(queue.expire(start + INITIAL_RTO / 2), None);
+        assert_eq!(
+            queue.expire(start + INITIAL_RTO),
+            Some(Expiry::Retransmit(syn.clone()))
+        );
+        assert_eq!(queue.retries(), 1);
+        assert_eq!(queue.deadline(), Some(start + INITIAL_RTO * 3));
+        // the retransmission itself is not queued again
+        queue.push(&syn, start + INITIAL_RTO);
+        assert_eq!(queue.len(), 1);
+        queue.acknowledge(
+            tcb.snd_nxt,
+            start + INITIAL_RTO + Duration::from_millis(300),
+        );
+        assert!(queue.is_empty());
+        assert_eq!(queue.retries(), 0);
+        assert_eq!(queue.estimator().srtt(), None);
+        assert_eq!(queue.estimator().rto(), INITIAL_RTO * 2);
+    }
+
+    #[test]
+    fn timer_gives_up_after_the_threshold() {
+        let mut tcb = tcb();
+        let mut queue = RetransmissionQueue::new(2);
+        let start = Instant::now();
+        let syn = tcb.segment(TcpFlags::SYN, &[]);
+        queue.push(&syn, start);
+        let first = start + INITIAL_RTO;
+        let second = first + INITIAL_RTO * 2;
+        let third = second + INITIAL_RTO * 4;
+        assert_eq!(queue.expire(first), Some(Expiry::Retransmit(syn.clone())));
+        assert_eq!(queue.expire(second), Some(Expiry::Retransmit(syn)));
+        assert_eq!(queue.expire(third), Some(Expiry::ThresholdExceeded));
+        assert_eq!(queue.deadline(), None);
+    }
+
+    #[test]
+    fn empty_segments_are_not_queued() {
+        let mut tcb = tcb();
+        let mut queue = RetransmissionQueue::default();
+        queue.push(&tcb.segment(TcpFlags::ACK, &[]), Instant::now());
+        assert!(queue.is_empty());
+        assert_eq!(queue.deadline(), None);
+    }
+}
@@ -0,0 +1,160 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+/// [SeqNum] is a TCP sequence number. Sequence space wraps around at 2^32, so arithmetic
+/// on it wraps and it is compared by serial number arithmetic as in RFC 1982, see [SeqNum::lt].
+/// That comparison is not a total order, so [SeqNum] does not implement [PartialOrd]:
+/// comparisons against a window are made with [SeqNum::between] instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SeqNum(u32);
+
+impl SeqNum {
+    #[must_use]
+    pub const fn new(value: u32) -> Self {
+        SeqNum(value)
+    }
+
+    /// The number as it is written in a segment header.
+    #[must_use]
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Whether `self` comes before `other`, i.e. `other` is less than 2^31 ahead of `self`,
+    /// as in RFC 1982. The comparison is undefined for two numbers exactly 2^31 apart,
+    /// for which neither comes before the other, and it is not transitive across more than
+    /// half of the sequence space.
+    #[must_use]
+    pub fn lt(self, other: SeqNum) -> bool {
+        let distance = other - self;
+        distance != 0 && distance < 0x8000_0000
+    }
+
+    /// Whether `self` is `other` or comes before it, see [SeqNum::lt] for the undefined case.
+    #[must_use]
+    pub fn le(self, other: SeqNum) -> bool {
+        self == other || self.lt(other)
+    }
+
+    /// Whether `low <= self <= high`, going forward from `low` to `high`,
+    /// e.g. `SND.UNA =< SEG.ACK =< SND.NXT`. Unlike [SeqNum::lt] this is defined
+    /// for any two bounds, as the window from `low` to `high` is at most the whole space.
+    #[must_use]
+    pub fn between(self, low: SeqNum, high: SeqNum) -> bool {
+        self - low <= high - low
+    }
+
+    /// Whether the number lies in the `length` numbers from `start` on,
+    /// i.e. `start <= self < start + length`, so an empty window holds nothing.
+    #[must_use]
+    pub fn in_window(self, start: SeqNum, length: u32) -> bool {
+        self - start < length
+    }
+}
+
+impl From<u32> for SeqNum {
+    fn from(value: u32) -> Self {
+        SeqNum(value)
+    }
+}
+
+impl From<SeqNum> for u32 {
+    fn from(value: SeqNum) -> Self {
+        value.0
+    }
+}
+
+impl Add<u32> for SeqNum {
+    type Output = SeqNum;
+
+    fn add(self, rhs: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_add(rhs))
+    }
+}
+
+impl AddAssign<u32> for SeqNum {
+    fn add_assign(&mut self, rhs: u32) {
+        *self = *self + rhs;
+    }
+}
+
+/// The distance from `rhs` forward to `self` in sequence space.
+impl Sub for SeqNum {
+    type Output = u32;
+
+    fn sub(self, rhs: SeqNum) -> u32 {
+        self.0.wrapping_sub(rhs.0)
+    }
+}
+
+impl fmt::Display for SeqNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeqNum;
+
+    #[test]
+    fn comparison_wraps_around() {
+        let last = SeqNum::new(u32::MAX);
+        let first = last + 1;
+        assert_eq!(first, SeqNum::new(0));
+        assert!(last.lt(first));
+        assert!(!first.lt(last));
+        assert!(last.le(first));
+        assert_eq!(first - last, 1);
+        assert!(SeqNum::new(u32::MAX - 10).lt(SeqNum::new(10)));
+    }
+
+    #[test]
+    fn comparison_holds_up_to_half_the_space() {
+        let start = SeqNum::new(0xf000_0000);
+        let ahead = start + 0x7fff_ffff;
+        assert!(start.lt(ahead));
+        assert!(!ahead.lt(start));
+        assert!(!ahead.le(start));
+        assert!(!start.lt(start));
+        assert!(start.le(start));
+    }
+
+    #[test]
+    fn comparison_is_undefined_at_half_the_space() {
+        let start = SeqNum::new(0xf000_0000);
+        let opposite = start + 0x8000_0000;
+        assert!(!start.lt(opposite));
+        assert!(!opposite.lt(start));
+        assert!(!start.le(opposite));
+        assert!(!opposite.le(start));
+    }
+
+    #[test]
+    fn between_is_defined_for_any_window() {
+        let low = SeqNum::new(u32::MAX - 1);
+        let high = low + 0x8000_0000;
+        assert!(low.between(low, high));
+        assert!(high.between(low, high));
+        assert!((low + 2).between(low, high));
+        assert!(!(low + u32::MAX).between(low, high));
+        assert!(!(high + 1).between(low, high));
+        assert!(low.between(low, low));
+        assert!(!(low + 1).between(low, low));
+    }
+}
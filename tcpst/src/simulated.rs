@@ -0,0 +1,766 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::{
+    collections::BTreeMap,
+    marker::PhantomData,
+    net::SocketAddrV4,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use pnet::packet::tcp::TcpPacket;
+
+use crate::{
+    net_channel::verify_checksum, Action, Branch, Choice, Message, OfferN, OfferOne, OfferTwo,
+    OfferedTwo, Role, SelectN, SelectOne, SelectTwo, SessionError, SessionGuard,
+    SessionTypedChannel, Timed, TimedBranch, TimedOfferedTwo, Token,
+};
+
+/// [Conditions] of the links of a simulated [Network], the same for every segment.
+/// Probabilities are between `0.0` and `1.0`, and the default is a perfect link without delay.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Conditions {
+    /// The probability that a segment is lost.
+    pub loss: f64,
+    /// The probability that a segment is delivered twice.
+    pub duplication: f64,
+    /// The probability that a segment is held back for an extra `delay + jitter`,
+    /// so that the segments sent right after it overtake it.
+    pub reordering: f64,
+    /// The probability that a bit of a segment is flipped on the way.
+    /// Receivers discard corrupted segments by their checksum, as a receiving TCP would.
+    pub corruption: f64,
+    /// The time every segment takes to arrive.
+    pub delay: Duration,
+    /// An extra delay of up to `jitter`, drawn for every segment.
+    pub jitter: Duration,
+}
+
+/// [Rng] is a SplitMix64 generator, so that a seed gives the same network on every platform.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            0
+        } else {
+            self.next() % bound
+        }
+    }
+}
+
+/// [State] of a [Network], shared by its endpoints.
+struct State {
+    now: Duration,
+    rng: Rng,
+    conditions: Conditions,
+    /// The segments on their way, by time of arrival and order of sending.
+    in_flight: BTreeMap<(Duration, u64), (SocketAddrV4, Vec<u8>)>,
+    sent: u64,
+    /// The endpoints that are alive, each with its deadline if it is waiting for a segment,
+    /// or [Duration::MAX] if it waits without one.
+    endpoints: BTreeMap<SocketAddrV4, Option<Duration>>,
+}
+
+impl State {
+    fn transmit(&mut self, destination: SocketAddrV4, mut packet: Vec<u8>) {
+        let conditions = self.conditions;
+        if self.rng.chance(conditions.loss) {
+            return;
+        }
+        if self.rng.chance(conditions.corruption) && !packet.is_empty() {
+            let bit = self.rng.below(packet.len() as u64 * 8);
+            packet[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+        let copies = if self.rng.chance(conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let jitter = self.jitter();
+            let mut delay = conditions.delay + jitter;
+            if self.rng.chance(conditions.reordering) {
+                delay += conditions.delay + conditions.jitter;
+            }
+            self.in_flight
+                .insert((self.now + delay, self.sent), (destination, packet.clone()));
+            self.sent += 1;
+        }
+    }
+
+    fn jitter(&mut self) -> Duration {
+        let nanos = u64::try_from(self.conditions.jitter.as_nanos()).unwrap_or(u64::MAX);
+        Duration::from_nanos(self.rng.below(nanos.saturating_add(1)))
+    }
+
+    /// Take the first segment for `address` that has arrived by now.
+    fn arrived(&mut self, address: SocketAddrV4) -> Option<Vec<u8>> {
+        let key = *self
+            .in_flight
+            .iter()
+            .find(|((at, _), (destination, _))| *at <= self.now && *destination == address)?
+            .0;
+        self.in_flight.remove(&key).map(|(_, packet)| packet)
+    }
+
+    /// Whether an endpoint has a segment or a deadline it has not seen yet.
+    fn pending(&self) -> bool {
+        self.in_flight.iter().any(|((at, _), (destination, _))| {
+            *at <= self.now && self.endpoints.contains_key(destination)
+        }) || self
+            .endpoints
+            .values()
+            .flatten()
+            .any(|deadline| *deadline <= self.now)
+    }
+
+    /// When every endpoint waits and has seen everything up to now, nothing can be sent
+    /// until the clock moves, so move it to the next arrival or deadline.
+    /// Returns false if there is neither, and the endpoints would wait forever.
+    fn advance(&mut self) -> bool {
+        if self.endpoints.values().any(Option::is_none) || self.pending() {
+            return true;
+        }
+        let arrival = self
+            .in_flight
+            .iter()
+            .filter(|(_, (destination, _))| self.endpoints.contains_key(destination))
+            .map(|((at, _), _)| *at)
+            .min();
+        let deadline = self
+            .endpoints
+            .values()
+            .flatten()
+            .copied()
+            .filter(|deadline| *deadline != Duration::MAX)
+            .min();
+        match arrival.into_iter().chain(deadline).min() {
+            Some(next) => {
+                self.now = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// [Network] is an in-process IP network whose endpoints exchange raw TCP segments,
+/// with the losses, duplicates, reordering, delay and corruption of its [Conditions].
+/// Everything that happens on it is decided by a seeded generator and a virtual clock,
+/// so a run is repeated exactly by running it with the same seed.
+///
+/// The clock only moves when every endpoint of the network is waiting for a segment:
+/// it jumps to the next arrival or receive deadline, whichever comes first.
+/// An endpoint that is busy elsewhere, e.g. waiting for its user, holds the clock still.
+/// If every endpoint waits without a deadline and nothing is on its way, they fail with
+/// [SessionError::Disconnected] instead of waiting forever.
+#[derive(Clone)]
+pub struct Network {
+    shared: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Network {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Network {
+            shared: Arc::new((
+                Mutex::new(State {
+                    now: Duration::ZERO,
+                    rng: Rng(seed),
+                    conditions: Conditions::default(),
+                    in_flight: BTreeMap::new(),
+                    sent: 0,
+                    endpoints: BTreeMap::new(),
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    #[must_use]
+    pub fn with_conditions(self, conditions: Conditions) -> Self {
+        self.state().conditions = conditions;
+        self
+    }
+
+    /// The time on the virtual clock since the network was created.
+    #[must_use]
+    pub fn now(&self) -> Duration {
+        self.state().now
+    }
+
+    /// The endpoint of role `R1` at `local`, talking to role `R2` at `remote`.
+    /// Panics if there is already an endpoint at `local`.
+    #[must_use]
+    pub fn channel<R1, R2>(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+    ) -> SimulatedChannel<R1, R2>
+    where
+        R1: Role,
+        R2: Role,
+    {
+        let previous = self.state().endpoints.insert(local, None);
+        assert!(previous.is_none(), "{local} already has an endpoint");
+        SimulatedChannel {
+            network: self.clone(),
+            local,
+            remote,
+            guard: SessionGuard::new::<SimulatedChannel<R1, R2>>(),
+            phantom: PhantomData,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// [SimulatedChannel] is a session-typed channel over a simulated [Network],
+/// carrying the same segments as [crate::net_channel::NetChannel] without raw sockets.
+/// [SimulatedChannel] behaves as any other session-typed channels and implements [SessionTypedChannel].
+pub struct SimulatedChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    network: Network,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    guard: SessionGuard,
+    phantom: PhantomData<(R1, R2)>,
+}
+
+impl<R1, R2> SimulatedChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    /// The network the channel is an endpoint of.
+    #[must_use]
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    fn send_segment(&mut self, packet: Vec<u8>) {
+        self.network.state().transmit(self.remote, packet);
+        self.network.shared.1.notify_all();
+    }
+
+    /// The deadline `timeout` from now on the virtual clock.
+    fn deadline(&self, timeout: Duration) -> Option<Duration> {
+        Some(self.network.now() + timeout)
+    }
+
+    /// Read the next segment addressed to us, or [None] if `deadline` passes first on the virtual clock.
+    /// Segments with an invalid checksum are discarded.
+    fn recv_segment(
+        &mut self,
+        deadline: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>, SessionError> {
+        let deadline = deadline.unwrap_or(Duration::MAX);
+        let (_, condvar) = &*self.network.shared;
+        let mut state = self.network.state();
+        let received = loop {
+            if let Some(packet) = state.arrived(self.local) {
+                if self.intact(&packet) {
+                    break Ok(Some(packet));
+                }
+                continue;
+            }
+            if state.now >= deadline {
+                break Ok(None);
+            }
+            state.endpoints.insert(self.local, Some(deadline));
+            let now = state.now;
+            if !state.advance() {
+                break Err(SessionError::Disconnected);
+            }
+            if state.now != now {
+                // the clock moved, someone may have a segment or a deadline now
+                condvar.notify_all();
+                continue;
+            }
+            state = condvar.wait(state).unwrap_or_else(|e| e.into_inner());
+        };
+        state.endpoints.insert(self.local, None);
+        received
+    }
+
+    fn intact(&self, packet: &[u8]) -> bool {
+        TcpPacket::new(packet).is_some_and(|segment| {
+            verify_checksum(&segment, self.remote.ip(), self.local.ip()).is_ok()
+        })
+    }
+
+    fn offer_one_until<M>(&mut self, deadline: Option<Duration>) -> Result<Option<M>, SessionError>
+    where
+        M: Message,
+    {
+        // as on a real network, segments that are not the offered message are rejected
+        while let Some(slice) = self.recv_segment(deadline)? {
+            if M::matches(&slice) {
+                return Ok(Some(M::from_net_representation(slice)?));
+            }
+        }
+        Ok(None)
+    }
+
+    fn offer_two_until<M1, M2, A1, A2>(
+        &mut self,
+        deadline: Option<Duration>,
+    ) -> Result<Option<OfferedTwo<M1, M2, A1, A2>>, SessionError>
+    where
+        M1: Message,
+        M2: Message,
+        A1: Action,
+        A2: Action,
+    {
+        // as on a real network, segments that are neither of the offered messages are rejected
+        while let Some(slice) = self.recv_segment(deadline)? {
+            if M1::matches(&slice) {
+                let message = M1::from_net_representation(slice)?;
+                return Ok(Some(Branch::Left((message, A1::new(Token::new())))));
+            }
+            if M2::matches(&slice) {
+                let message = M2::from_net_representation(slice)?;
+                return Ok(Some(Branch::Right((message, A2::new(Token::new())))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn offer_n_until<C>(&mut self, deadline: Option<Duration>) -> Result<Option<C>, SessionError>
+    where
+        C: Choice,
+    {
+        while let Some(slice) = self.recv_segment(deadline)? {
+            if let Some(label) = C::label_of(&slice) {
+                return Ok(Some(C::from_net_representation(
+                    label,
+                    slice,
+                    Token::new(),
+                )?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R1, R2> Drop for SimulatedChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    fn drop(&mut self) {
+        self.network.state().endpoints.remove(&self.local);
+        self.network.shared.1.notify_all();
+    }
+}
+
+impl<R1, R2> SessionTypedChannel<R1, R2> for SimulatedChannel<R1, R2>
+where
+    R1: Role,
+    R2: Role,
+{
+    fn start<A>(&mut self) -> Result<A, SessionError>
+    where
+        A: Action,
+    {
+        self.guard.start()
+    }
+
+    fn delegate(&mut self, _token: Token) {
+        self.guard.delegate();
+    }
+
+    fn offer_one<M, A>(&mut self, _o: OfferOne<R2, M, A>) -> Result<(M, A), SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+    {
+        match self.offer_one_until(None)? {
+            Some(message) => Ok((message, A::new(Token::new()))),
+            None => Err(SessionError::Timeout),
+        }
+    }
+
+    fn select_one<M, A>(&mut self, _o: SelectOne<R2, M, A>, message: M) -> Result<A, SessionError>
+    where
+        M: Message,
+        A: Action,
+    {
+        self.send_segment(message.to_net_representation());
+        Ok(A::new(Token::new()))
+    }
+
+    fn offer_two<M1, M2, A1, A2>(
+        &mut self,
+        _o: OfferTwo<R2, M1, M2, A1, A2>,
+    ) -> Result<OfferedTwo<M1, M2, A1, A2>, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        match self.offer_two_until(None)? {
+            Some(branch) => Ok(branch),
+            None => Err(SessionError::Timeout),
+        }
+    }
+
+    fn select_left<M1, M2, A1, A2>(
+        &mut self,
+        _o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M1,
+    ) -> Result<A1, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        self.send_segment(message.to_net_representation());
+        Ok(A1::new(Token::new()))
+    }
+
+    fn select_right<M1, M2, A1, A2>(
+        &mut self,
+        _o: SelectTwo<R2, M1, M2, A1, A2>,
+        message: M2,
+    ) -> Result<A2, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+    {
+        self.send_segment(message.to_net_representation());
+        Ok(A2::new(Token::new()))
+    }
+
+    fn offer_n<C>(&mut self, _o: OfferN<R2, C>) -> Result<C, SessionError>
+    where
+        C: Choice,
+    {
+        match self.offer_n_until(None)? {
+            Some(branch) => Ok(branch),
+            None => Err(SessionError::Timeout),
+        }
+    }
+
+    fn select_n<C, M, A>(
+        &mut self,
+        _o: SelectN<R2, C>,
+        label: fn(M, A) -> C,
+        message: M,
+    ) -> Result<A, SessionError>
+    where
+        C: Choice,
+        M: Message + 'static,
+        A: Action,
+    {
+        self.send_segment(label(message, A::new(Token::new())).to_net_representation());
+        Ok(A::new(Token::new()))
+    }
+
+    /// The timeout is measured on the virtual clock of the network.
+    fn offer_one_timeout<M, A, T>(
+        &mut self,
+        _o: Timed<OfferOne<R2, M, A>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<(M, A), T>, SessionError>
+    where
+        M: Message + 'static,
+        A: Action + 'static,
+        T: Action,
+    {
+        match self.offer_one_until(self.deadline(timeout))? {
+            Some(message) => Ok(TimedBranch::Received((message, A::new(Token::new())))),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_two_timeout<M1, M2, A1, A2, T>(
+        &mut self,
+        _o: Timed<OfferTwo<R2, M1, M2, A1, A2>, T>,
+        timeout: Duration,
+    ) -> Result<TimedOfferedTwo<M1, M2, A1, A2, T>, SessionError>
+    where
+        M1: Message + 'static,
+        M2: Message + 'static,
+        A1: Action,
+        A2: Action,
+        T: Action,
+    {
+        match self.offer_two_until(self.deadline(timeout))? {
+            Some(branch) => Ok(TimedBranch::Received(branch)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn offer_n_timeout<C, T>(
+        &mut self,
+        _o: Timed<OfferN<R2, C>, T>,
+        timeout: Duration,
+    ) -> Result<TimedBranch<C, T>, SessionError>
+    where
+        C: Choice,
+        T: Action,
+    {
+        match self.offer_n_until(self.deadline(timeout))? {
+            Some(branch) => Ok(TimedBranch::Received(branch)),
+            None => Ok(TimedBranch::TimedOut(T::new(Token::new()))),
+        }
+    }
+
+    fn close(mut self, _end: crate::End) -> Result<(), SessionError> {
+        self.guard.close();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        thread,
+        time::Duration,
+    };
+
+    use pnet::packet::tcp::TcpFlags;
+
+    use super::{Conditions, Network};
+    use crate::{
+        sequence::SeqNum,
+        sorts::{SegAckSet, SegFinSet, SegSynAckSet, SegSynSet},
+        tcb::Tcb,
+        Branch, End, OfferOne, OfferTwo, Rec, RecursionVariable, Role, SelectOne, SessionError,
+        SessionTypedChannel, Timed, TimedBranch, Var,
+    };
+
+    struct RoleServerSystem;
+    impl Role for RoleServerSystem {}
+
+    struct RoleClientSystem;
+    impl Role for RoleClientSystem {}
+
+    // The sessions of the server and the client in the handshake of `tcp_handshake.global`,
+    // followed by the close of the server, where the client sends its SYN again if no SYN-ACK
+    // arrives in time, and the server answers every SYN with its SYN-ACK.
+    type ServerSession = OfferOne<RoleClientSystem, SegSynSet, Rec<ServerHandshake>>;
+
+    struct ServerHandshake;
+    impl RecursionVariable for ServerHandshake {
+        type Body = SelectOne<
+            RoleClientSystem,
+            SegSynAckSet,
+            OfferTwo<
+                RoleClientSystem,
+                SegAckSet,
+                SegSynSet,
+                SelectOne<RoleClientSystem, SegFinSet, End>,
+                Var<ServerHandshake>,
+            >,
+        >;
+    }
+
+    type ClientSession = SelectOne<RoleServerSystem, SegSynSet, Rec<ClientHandshake>>;
+
+    struct ClientHandshake;
+    impl RecursionVariable for ClientHandshake {
+        type Body = Timed<
+            OfferOne<
+                RoleServerSystem,
+                SegSynAckSet,
+                SelectOne<RoleServerSystem, SegAckSet, OfferOne<RoleServerSystem, SegFinSet, End>>,
+            >,
+            SelectOne<RoleServerSystem, SegSynSet, Var<ClientHandshake>>,
+        >;
+    }
+
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156);
+    const SERVER_ISS: u32 = 1000;
+    const CLIENT_ISS: u32 = 5000;
+    const SYN_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Run the handshake and close on `network`, and return the TCBs of the server
+    /// and the client with the number of SYNs the client sent.
+    fn handshake_and_close(network: &Network) -> Result<(Tcb, Tcb, u32), SessionError> {
+        let mut server = network.channel::<RoleServerSystem, RoleClientSystem>(SERVER, CLIENT);
+        let mut client = network.channel::<RoleClientSystem, RoleServerSystem>(CLIENT, SERVER);
+        thread::scope(|scope| {
+            let server = scope.spawn(move || -> Result<Tcb, SessionError> {
+                let mut tcb = Tcb::new(SERVER, CLIENT, SeqNum::new(SERVER_ISS));
+                let st = server.start::<ServerSession>()?;
+                let (syn, cont) = server.offer_one(st)?;
+                tcb.receive(&syn.packet);
+                let syn_ack = tcb.segment(TcpFlags::SYN | TcpFlags::ACK, &[]);
+                let mut cont = server.enter(cont)?;
+                loop {
+                    let packet = syn_ack.clone();
+                    let reply = server.select_one(cont, SegSynAckSet { packet })?;
+                    match server.offer_two(reply)? {
+                        Branch::Left((ack, cont)) => {
+                            tcb.receive(&ack.packet);
+                            let packet = tcb.segment(TcpFlags::FIN | TcpFlags::ACK, &[]);
+                            let cont = server.select_one(cont, SegFinSet { packet })?;
+                            server.close(cont)?;
+                            return Ok(tcb);
+                        }
+                        Branch::Right((_, retry)) => cont = server.recurse(retry)?,
+                    }
+                }
+            });
+            let client = scope.spawn(move || -> Result<(Tcb, u32), SessionError> {
+                let mut tcb = Tcb::new(CLIENT, SERVER, SeqNum::new(CLIENT_ISS));
+                let syn = tcb.segment(TcpFlags::SYN, &[]);
+                let mut syns = 1;
+                let st = client.start::<ClientSession>()?;
+                let cont = client.select_one(
+                    st,
+                    SegSynSet {
+                        packet: syn.clone(),
+                    },
+                )?;
+                let mut cont = client.enter(cont)?;
+                let cont = loop {
+                    match client.offer_one_timeout(cont, SYN_TIMEOUT)? {
+                        TimedBranch::Received((syn_ack, cont)) => {
+                            tcb.receive(&syn_ack.packet);
+                            break cont;
+                        }
+                        TimedBranch::TimedOut(retry) => {
+                            syns += 1;
+                            let retry = client.select_one(
+                                retry,
+                                SegSynSet {
+                                    packet: syn.clone(),
+                                },
+                            )?;
+                            cont = client.recurse(retry)?;
+                        }
+                    }
+                };
+                let packet = tcb.segment(TcpFlags::ACK, &[]);
+                let cont = client.select_one(cont, SegAckSet { packet })?;
+                let (fin, cont) = client.offer_one(cont)?;
+                tcb.receive(&fin.packet);
+                client.close(cont)?;
+                Ok((tcb, syns))
+            });
+            let server = server.join().expect("the server does not panic")?;
+            let (client, syns) = client.join().expect("the client does not panic")?;
+            Ok((server, client, syns))
+        })
+    }
+
+    /// Both ends are synchronized, and the FIN of the server is received but not acknowledged.
+    fn assert_closed(server: &Tcb, client: &Tcb) {
+        assert_eq!(server.snd_una, SeqNum::new(SERVER_ISS + 1));
+        assert_eq!(server.snd_nxt, SeqNum::new(SERVER_ISS + 2));
+        assert_eq!(server.irs, SeqNum::new(CLIENT_ISS));
+        assert_eq!(server.rcv_nxt, SeqNum::new(CLIENT_ISS + 1));
+        assert_eq!(client.snd_una, SeqNum::new(CLIENT_ISS + 1));
+        assert_eq!(client.snd_nxt, SeqNum::new(CLIENT_ISS + 1));
+        assert_eq!(client.irs, SeqNum::new(SERVER_ISS));
+        assert_eq!(client.rcv_nxt, SeqNum::new(SERVER_ISS + 2));
+    }
+
+    #[test]
+    fn handshake_and_close_on_a_perfect_link() {
+        let network = Network::new(1).with_conditions(Conditions {
+            delay: Duration::from_millis(10),
+            ..Conditions::default()
+        });
+        let (server, client, syns) = handshake_and_close(&network).unwrap();
+        assert_closed(&server, &client);
+        assert_eq!(syns, 1);
+        // SYN, SYN-ACK, ACK and FIN-ACK, one after the other
+        assert_eq!(network.now(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn handshake_and_close_with_loss_and_reordering() {
+        // a seed that loses the first SYN or its SYN-ACK, so the client sends a second SYN
+        const SEED: u64 = 13;
+        let conditions = Conditions {
+            loss: 0.3,
+            reordering: 0.3,
+            delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(5),
+            ..Conditions::default()
+        };
+        let network = Network::new(SEED).with_conditions(conditions);
+        let (server, client, syns) = handshake_and_close(&network).unwrap();
+        assert_closed(&server, &client);
+        assert_eq!(syns, 2);
+        assert!(network.now() > SYN_TIMEOUT);
+        let now = network.now();
+        assert_eq!(now, Duration::from_nanos(150_832_147));
+
+        // the same seed is the same run
+        let network = Network::new(SEED).with_conditions(conditions);
+        handshake_and_close(&network).unwrap();
+        assert_eq!(network.now(), now);
+    }
+
+    #[test]
+    fn offer_one_skips_segments_that_are_not_the_offered_message() {
+        let network = Network::new(1);
+        let mut server = network.channel::<RoleServerSystem, RoleClientSystem>(SERVER, CLIENT);
+        let mut client = network.channel::<RoleClientSystem, RoleServerSystem>(CLIENT, SERVER);
+        let mut tcb = Tcb::new(CLIENT, SERVER, SeqNum::new(CLIENT_ISS));
+        // a stray ACK arrives before the SYN the server is waiting for
+        let stray = tcb.segment(TcpFlags::ACK, &[]);
+        let syn = tcb.segment(TcpFlags::SYN, &[]);
+        let st = client
+            .start::<SelectOne<RoleServerSystem, SegAckSet, SelectOne<RoleServerSystem, SegSynSet, End>>>()
+            .unwrap();
+        let cont = client.select_one(st, SegAckSet { packet: stray }).unwrap();
+        let cont = client
+            .select_one(
+                cont,
+                SegSynSet {
+                    packet: syn.clone(),
+                },
+            )
+            .unwrap();
+        client.close(cont).unwrap();
+
+        let st = server
+            .start::<OfferOne<RoleClientSystem, SegSynSet, End>>()
+            .unwrap();
+        let (received, cont) = server.offer_one(st).unwrap();
+        assert_eq!(received.packet, syn);
+        server.close(cont).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+/**
+ * Copyright 2023, Ivan Nikitin.
+ * This file is part of TCP-ST.
+ *
+ * TCP-ST is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+ *
+ * TCP-ST is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ * See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with TCP-ST.
+ * If not, see <https://www.gnu.org/licenses/>.
+ *
+ */
+use std::net::SocketAddrV4;
+
+use pnet::packet::{
+    tcp::{ipv4_checksum, MutableTcpPacket, TcpFlags, TcpPacket},
+    Packet,
+};
+
+use crate::{isn::IsnSource, sequence::SeqNum};
+
+/// The receive window a [Tcb] starts with, the largest that fits in the header without window scaling.
+pub const DEFAULT_WINDOW: u32 = u16::MAX as u32;
+
+/// [Acceptability] is the verdict of the acceptability test of RFC 9293 section 3.10.7.4
+/// on a received segment, see [Tcb::acceptability].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acceptability {
+    /// The segment overlaps the receive window, or is empty and at RCV.NXT, and is processed.
+    Acceptable,
+    /// The segment lies entirely before RCV.NXT, it was received before.
+    Duplicate,
+    /// The segment starts beyond the receive window, or carries data into a closed window.
+    OutOfWindow,
+    /// The segment is an unacceptable RST.
+    Reset,
+}
+
+impl Acceptability {
+    /// Whether the segment is dropped and answered with an acknowledgement of RCV.NXT,
+    /// which is the case for every unacceptable segment but a RST, which is only dropped.
+    #[must_use]
+    pub fn needs_ack(self) -> bool {
+        matches!(self, Acceptability::Duplicate | Acceptability::OutOfWindow)
+    }
+}
+
+/// [Tcb] is the Transmission Control Block of a connection, with the state variables
+/// of RFC 9293 section 3.3.1. The segments of the connection are built from it with
+/// [Tcb::segment], received segments are checked with [Tcb::acceptability]
+/// and accounted for with [Tcb::receive].
+///
+/// A passive open creates the block when the SYN arrives, which names the remote socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tcb {
+    /// The local socket of the connection.
+    pub local: SocketAddrV4,
+    /// The remote socket of the connection.
+    pub remote: SocketAddrV4,
+    /// SND.UNA, the oldest unacknowledged sequence number.
+    pub snd_una: SeqNum,
+    /// SND.NXT, the next sequence number to be sent.
+    pub snd_nxt: SeqNum,
+    /// SND.WND, the window advertised by the remote TCP.
+    pub snd_wnd: u32,
+    /// SND.WL1, the sequence number of the segment of the last window update.
+    pub snd_wl1: SeqNum,
+    /// SND.WL2, the acknowledgement number of the segment of the last window update.
+    pub snd_wl2: SeqNum,
+    /// ISS, the initial send sequence number.
+    pub iss: SeqNum,
+    /// RCV.NXT, the next sequence number expected.
+    pub rcv_nxt: SeqNum,
+    /// RCV.WND, the window advertised to the remote TCP.
+    pub rcv_wnd: u32,
+    /// IRS, the initial receive sequence number.
+    pub irs: SeqNum,
+    /// The sequence number that acknowledges the FIN sent, once one was sent.
+    pub fin: Option<SeqNum>,
+}
+
+impl Tcb {
+    /// The block of a connection from `local` to `remote` that starts sending at `iss`.
+    #[must_use]
+    pub fn new(local: SocketAddrV4, remote: SocketAddrV4, iss: SeqNum) -> Self {
+        Tcb {
+            local,
+            remote,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            snd_wl1: SeqNum::default(),
+            snd_wl2: SeqNum::default(),
+            iss,
+            rcv_nxt: SeqNum::default(),
+            rcv_wnd: DEFAULT_WINDOW,
+            irs: SeqNum::default(),
+            fin: None,
+        }
+    }
+
+    /// The block of a connection from `local` to `remote`, whose ISS is picked by `isn`.
+    pub fn open<I>(local: SocketAddrV4, remote: SocketAddrV4, isn: &mut I) -> Self
+    where
+        I: IsnSource,
+    {
+        Self::new(local, remote, isn.isn(local, remote))
+    }
+
+    /// Build the next segment with `flags` and `payload`, at SND.NXT and acknowledging RCV.NXT
+    /// if `flags` has ACK, and advance SND.NXT past it, remembering where a FIN ends.
+    pub fn segment(&mut self, flags: u16, payload: &[u8]) -> Vec<u8> {
+        let mut vec = vec![0; TcpPacket::minimum_packet_size() + payload.len()];
+        let mut packet = MutableTcpPacket::new(&mut vec).expect("the buffer fits a header");
+        packet.set_source(self.local.port());
+        packet.set_destination(self.remote.port());
+        packet.set_sequence(self.snd_nxt.get());
+        if flags & TcpFlags::ACK != 0 {
+            packet.set_acknowledgement(self.rcv_nxt.get());
+        }
+        packet.set_flags(flags);
+        packet.set_window(u16::try_from(self.rcv_wnd).unwrap_or(u16::MAX));
+        packet.set_data_offset(5);
+        packet.set_payload(payload);
+        let checksum = ipv4_checksum(&packet.to_immutable(), self.local.ip(), self.remote.ip());
+        packet.set_checksum(checksum);
+        self.snd_nxt += length(&packet.to_immutable());
+        if flags & TcpFlags::FIN != 0 {
+            self.fin = Some(self.snd_nxt);
+        }
+        vec
+    }
+
+    /// Whether a received segment acknowledges the FIN sent, which makes
+    /// a FIN of the remote TCP its reply to our close rather than a close of its own.
+    #[must_use]
+    pub fn acknowledges_fin(&self, segment: &TcpPacket) -> bool {
+        segment.get_flags() & TcpFlags::ACK != 0
+            && self.fin == Some(SeqNum::from(segment.get_acknowledgement()))
+    }
+
+    /// Test whether a received segment falls into the receive window, with the four cases
+    /// of RFC 9293 section 3.10.7.4 for empty and non-empty segments and windows.
+    ///
+    /// Before the connection is synchronized there is no receive window yet,
+    /// so a SYN-ACK that acknowledges the SYN sent is acceptable.
+    #[must_use]
+    pub fn acceptability(&self, segment: &TcpPacket) -> Acceptability {
+        let sequence = SeqNum::from(segment.get_sequence());
+        let flags = segment.get_flags();
+        if flags & TcpFlags::SYN != 0
+            && flags & TcpFlags::ACK != 0
+            && self.snd_una == self.iss
+            && SeqNum::from(segment.get_acknowledgement()) == self.iss + 1
+        {
+            return Acceptability::Acceptable;
+        }
+        let length = length(segment);
+        let acceptable = match (length, self.rcv_wnd) {
+            (0, 0) => sequence == self.rcv_nxt,
+            (0, _) => sequence.in_window(self.rcv_nxt, self.rcv_wnd),
+            (_, 0) => false,
+            (_, _) => {
+                sequence.in_window(self.rcv_nxt, self.rcv_wnd)
+                    || (sequence + (length - 1)).in_window(self.rcv_nxt, self.rcv_wnd)
+            }
+        };
+        if acceptable {
+            Acceptability::Acceptable
+        } else if flags & TcpFlags::RST != 0 {
+            Acceptability::Reset
+        } else if (sequence + length).le(self.rcv_nxt) {
+            Acceptability::Duplicate
+        } else {
+            Acceptability::OutOfWindow
+        }
+    }
+
+    /// Account for a received segment: a SYN sets IRS and RCV.NXT, a segment that
+    /// covers RCV.NXT advances it past its data and FIN, and an acknowledgement of new data advances
+    /// SND.UNA and updates the send window as in RFC 9293 section 3.10.7.4.
+    pub fn receive(&mut self, packet: &[u8]) {
+        let Some(segment) = TcpPacket::new(packet) else {
+            return;
+        };
+        let sequence = SeqNum::from(segment.get_sequence());
+        let acknowledgement = SeqNum::from(segment.get_acknowledgement());
+        let window = u32::from(segment.get_window());
+        let flags = segment.get_flags();
+        if flags & TcpFlags::SYN != 0 {
+            self.irs = sequence;
+            self.rcv_nxt = sequence + length(&segment);
+            self.snd_wnd = window;
+            self.snd_wl1 = sequence;
+            self.snd_wl2 = acknowledgement;
+        } else if self.rcv_nxt.in_window(sequence, length(&segment)) {
+            self.rcv_nxt = sequence + length(&segment);
+        }
+        if flags & TcpFlags::ACK == 0 || !acknowledgement.between(self.snd_una, self.snd_nxt) {
+            return;
+        }
+        self.snd_una = acknowledgement;
+        if self.snd_wl1.lt(sequence)
+            || (self.snd_wl1 == sequence && self.snd_wl2.le(acknowledgement))
+        {
+            self.snd_wnd = window;
+            self.snd_wl1 = sequence;
+            self.snd_wl2 = acknowledgement;
+        }
+    }
+}
+
+/// The length of `segment` in sequence space: its data, plus one for each of SYN and FIN.
+#[must_use]
+pub fn length(segment: &TcpPacket) -> u32 {
+    let flags = segment.get_flags();
+    segment.payload().len() as u32
+        + u32::from(flags & TcpFlags::SYN != 0)
+        + u32::from(flags & TcpFlags::FIN != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use pnet::packet::tcp::{TcpFlags, TcpPacket};
+
+    use super::{Acceptability, Tcb};
+    use crate::sequence::SeqNum;
+
+    const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    const REMOTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 49156);
+
+    /// A synchronized block expecting `rcv_nxt` next, with a receive window of `rcv_wnd`.
+    fn tcb(rcv_nxt: u32, rcv_wnd: u32) -> Tcb {
+        let mut tcb = Tcb::new(LOCAL, REMOTE, SeqNum::new(1000));
+        tcb.snd_una = tcb.iss + 1;
+        tcb.snd_nxt = tcb.iss + 1;
+        tcb.rcv_nxt = SeqNum::new(rcv_nxt);
+        tcb.rcv_wnd = rcv_wnd;
+        tcb
+    }
+
+    /// The verdict of `tcb` on a segment from the remote TCP at `sequence`.
+    fn verdict(tcb: &Tcb, sequence: u32, flags: u16, payload: &[u8]) -> Acceptability {
+        let segment = Tcb::new(REMOTE, LOCAL, SeqNum::new(sequence)).segment(flags, payload);
+        tcb.acceptability(&TcpPacket::new(&segment).expect("a whole segment"))
+    }
+
+    #[test]
+    fn empty_segment_in_a_closed_window() {
+        let tcb = tcb(5000, 0);
+        assert_eq!(
+            verdict(&tcb, 5000, TcpFlags::ACK, &[]),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 4999, TcpFlags::ACK, &[]),
+            Acceptability::Duplicate
+        );
+        assert_eq!(
+            verdict(&tcb, 5001, TcpFlags::ACK, &[]),
+            Acceptability::OutOfWindow
+        );
+    }
+
+    #[test]
+    fn empty_segment_in_an_open_window() {
+        let tcb = tcb(5000, 100);
+        assert_eq!(
+            verdict(&tcb, 5000, TcpFlags::ACK, &[]),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 5099, TcpFlags::ACK, &[]),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 4999, TcpFlags::ACK, &[]),
+            Acceptability::Duplicate
+        );
+        assert_eq!(
+            verdict(&tcb, 5100, TcpFlags::ACK, &[]),
+            Acceptability::OutOfWindow
+        );
+    }
+
+    #[test]
+    fn data_in_a_closed_window() {
+        let tcb = tcb(5000, 0);
+        assert_eq!(
+            verdict(&tcb, 5000, TcpFlags::ACK, b"data"),
+            Acceptability::OutOfWindow
+        );
+        assert_eq!(
+            verdict(&tcb, 4996, TcpFlags::ACK, b"data"),
+            Acceptability::Duplicate
+        );
+    }
+
+    #[test]
+    fn data_in_an_open_window() {
+        let tcb = tcb(5000, 100);
+        assert_eq!(
+            verdict(&tcb, 5000, TcpFlags::ACK, b"data"),
+            Acceptability::Acceptable
+        );
+        // the start is before the window, the end in it
+        assert_eq!(
+            verdict(&tcb, 4998, TcpFlags::ACK, b"data"),
+            Acceptability::Acceptable
+        );
+        // the start is in the window, the end beyond it
+        assert_eq!(
+            verdict(&tcb, 5098, TcpFlags::ACK, b"data"),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 4996, TcpFlags::ACK, b"data"),
+            Acceptability::Duplicate
+        );
+        assert_eq!(
+            verdict(&tcb, 5100, TcpFlags::ACK, b"data"),
+            Acceptability::OutOfWindow
+        );
+    }
+
+    #[test]
+    fn unacceptable_reset_is_not_acknowledged() {
+        let tcb = tcb(5000, 100);
+        assert_eq!(
+            verdict(&tcb, 5000, TcpFlags::RST, &[]),
+            Acceptability::Acceptable
+        );
+        let reset = verdict(&tcb, 6000, TcpFlags::RST, &[]);
+        assert_eq!(reset, Acceptability::Reset);
+        assert!(!reset.needs_ack());
+        assert!(verdict(&tcb, 6000, TcpFlags::ACK, &[]).needs_ack());
+    }
+
+    #[test]
+    fn window_across_the_wrap_of_rcv_nxt() {
+        let mut tcb = tcb(u32::MAX - 1, 100);
+        assert_eq!(
+            verdict(&tcb, u32::MAX, TcpFlags::ACK, &[]),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 97, TcpFlags::ACK, &[]),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, 98, TcpFlags::ACK, &[]),
+            Acceptability::OutOfWindow
+        );
+        assert_eq!(
+            verdict(&tcb, u32::MAX - 3, TcpFlags::ACK, b"data"),
+            Acceptability::Acceptable
+        );
+        assert_eq!(
+            verdict(&tcb, u32::MAX - 5, TcpFlags::ACK, b"data"),
+            Acceptability::Duplicate
+        );
+
+        // data across the wrap advances RCV.NXT past it
+        let segment =
+            Tcb::new(REMOTE, LOCAL, SeqNum::new(u32::MAX - 1)).segment(TcpFlags::ACK, b"data");
+        tcb.receive(&segment);
+        assert_eq!(tcb.rcv_nxt, SeqNum::new(2));
+    }
+}